@@ -0,0 +1,52 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use verstappenbot::resample::{
+    channels_of, resample, stereo_to_mono, stereo_to_mono_scalar, MonoFrame, INPUT_SAMPLE_RATE,
+};
+
+/// 20 ms of 48 kHz stereo, what songbird hands us every tick.
+fn tick() -> Vec<i16> {
+    (0..1920)
+        .map(|index| ((index * 7919) % u16::MAX as usize) as i16)
+        .collect()
+}
+
+fn downmix(c: &mut Criterion) {
+    let input = tick();
+    let mut output = Vec::new();
+
+    c.bench_function("stereo_to_mono_scalar", |b| {
+        b.iter(|| stereo_to_mono_scalar(black_box(&input), &mut output))
+    });
+    c.bench_function("stereo_to_mono", |b| {
+        b.iter(|| stereo_to_mono(black_box(&input), &mut output))
+    });
+}
+
+fn resampling(c: &mut Criterion) {
+    let mut mono = Vec::new();
+    stereo_to_mono(&tick(), &mut mono);
+    let mut output = Vec::new();
+
+    c.bench_function("resample_16khz", |b| {
+        b.iter(|| resample(black_box(&mono), INPUT_SAMPLE_RATE, 16000, &mut output))
+    });
+    c.bench_function("resample_22050hz", |b| {
+        b.iter(|| resample(black_box(&mono), INPUT_SAMPLE_RATE, 22050, &mut output))
+    });
+}
+
+fn mono_frame(c: &mut Criterion) {
+    let input = tick();
+    let mut frame = MonoFrame::default();
+
+    c.bench_function("mono_frame_two_rates", |b| {
+        b.iter(|| {
+            frame.fill(black_box(&input), channels_of(&input));
+            black_box(frame.at_rate(16000).len());
+            black_box(frame.at_rate(8000).len());
+        })
+    });
+}
+
+criterion_group!(benches, downmix, resampling, mono_frame);
+criterion_main!(benches);
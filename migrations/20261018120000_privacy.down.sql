@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE listen_roles;
+
+DROP TABLE opted_out_users;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP TABLE auto_join_channels;
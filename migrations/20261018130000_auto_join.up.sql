@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE auto_join_channels (
+    id SERIAL PRIMARY KEY,
+    server_id VARCHAR(255) NOT NULL,
+    channel_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (server_id, channel_id)
+);
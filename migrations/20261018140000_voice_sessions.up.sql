@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE voice_sessions (
+    server_id VARCHAR(255) PRIMARY KEY,
+    channel_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE user_languages (
+    server_id VARCHAR(255) NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    language VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (server_id, user_id)
+);
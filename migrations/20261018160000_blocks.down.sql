@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE blocked_users;
+
+DROP TABLE blocked_guilds;
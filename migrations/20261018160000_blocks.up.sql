@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE blocked_guilds (
+    server_id VARCHAR(255) PRIMARY KEY,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE blocked_users (
+    user_id VARCHAR(255) PRIMARY KEY,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
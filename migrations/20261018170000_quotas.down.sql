@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE guild_quotas;
+
+ALTER TABLE sounds DROP COLUMN size;
@@ -0,0 +1,10 @@
+-- Add up migration script here
+-- Sizes of the existing sounds are filled in from the files at startup.
+ALTER TABLE sounds ADD COLUMN size BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE guild_quotas (
+    server_id VARCHAR(255) PRIMARY KEY,
+    max_sounds INTEGER,
+    max_bytes BIGINT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
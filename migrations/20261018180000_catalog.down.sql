@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP INDEX sounds_file_name;
+
+DROP INDEX sounds_content_hash;
+
+ALTER TABLE sounds DROP COLUMN catalog_id;
+
+ALTER TABLE sounds DROP COLUMN content_hash;
+
+DROP TABLE catalog_sounds;
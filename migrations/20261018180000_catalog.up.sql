@@ -0,0 +1,22 @@
+-- Add up migration script here
+CREATE TABLE catalog_sounds (
+    id SERIAL PRIMARY KEY,
+    prompt VARCHAR(255) NOT NULL,
+    language VARCHAR(255) NOT NULL,
+    file_name VARCHAR(255) NOT NULL,
+    content_hash VARCHAR(64) NOT NULL,
+    size BIGINT NOT NULL,
+    publisher_id VARCHAR(255) NOT NULL,
+    server_id VARCHAR(255) NOT NULL,
+    installs INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (content_hash, language, prompt)
+);
+
+-- Sha-256 of the file, missing for the sounds added before it was recorded.
+ALTER TABLE sounds ADD COLUMN content_hash VARCHAR(64);
+-- Kept when the catalog entry is removed, so the sound still shows where it came from.
+ALTER TABLE sounds ADD COLUMN catalog_id INTEGER;
+
+CREATE INDEX sounds_content_hash ON sounds (content_hash);
+CREATE INDEX sounds_file_name ON sounds (file_name);
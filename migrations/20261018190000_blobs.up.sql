@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Files are stored under their sha-256, shared by every sound and catalog entry with the same content.
+-- The existing files are renamed to their hash at startup.
+CREATE TABLE blobs (
+    content_hash VARCHAR(64) PRIMARY KEY,
+    size BIGINT NOT NULL,
+    refs INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Bitrate the blob was encoded to with Opus, NULL for files stored as they were uploaded.
+ALTER TABLE blobs ADD COLUMN bitrate INTEGER;
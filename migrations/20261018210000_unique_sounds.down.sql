@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE sounds DROP CONSTRAINT sounds_server_prompt_language;
@@ -0,0 +1,10 @@
+-- Add up migration script here
+-- A guild has at most one sound per prompt and language. The older duplicates are kept,
+-- the references of the removed ones are recounted at startup.
+DELETE FROM sounds newer USING sounds older
+WHERE newer.server_id = older.server_id
+    AND newer.prompt = older.prompt
+    AND newer.language = older.language
+    AND newer.id > older.id;
+
+ALTER TABLE sounds ADD CONSTRAINT sounds_server_prompt_language UNIQUE (server_id, prompt, language);
@@ -0,0 +1,464 @@
+//! Every setting of the bot, loaded once at startup.
+//!
+//! Settings are read from the TOML file at `CONFIG_PATH` (`config.toml` by default, see `config.example.toml`).
+//! The file is optional, anything missing from it falls back to the defaults below.
+//! Environment variables override the file, so secrets like `DISCORD_TOKEN` don't have to be written down.
+
+use std::{env, fmt::Display, fs, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::speech_to_text::ModelLanguage;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordSettings,
+    pub database: DatabaseSettings,
+    pub models: Vec<ModelSettings>,
+    pub storage: StorageSettings,
+    pub playback: PlaybackSettings,
+    pub voice: VoiceSettings,
+    pub recognition: RecognitionSettings,
+    pub http: HttpSettings,
+    pub logging: LoggingSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordSettings {
+    /// `DISCORD_TOKEN`
+    pub token: String,
+    /// `OWNER_ID`, comma separated.
+    pub owners: Vec<u64>,
+    pub prefix: String,
+    pub github_url: String,
+    pub invite_url: String,
+}
+
+impl Default for DiscordSettings {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            owners: Vec::new(),
+            prefix: ".".to_string(),
+            github_url: "https://github.com/112batuhan/verstappenbot".to_string(),
+            invite_url: "https://discord.com/oauth2/authorize?client_id=1213040318195437598&permissions=274914675712&scope=bot%20applications.commands".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    /// `DATABASE_URL`
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSettings {
+    pub language: ModelLanguage,
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    /// Where the uploaded sounds are kept.
+    pub songs_dir: PathBuf,
+    pub max_upload_bytes: u64,
+    /// Default quotas of a guild, owners can change them per guild.
+    pub max_sounds_per_guild: u64,
+    pub max_bytes_per_guild: u64,
+    /// Bits per second uploads are re-encoded to with Opus before they are stored.
+    pub opus_bitrate: i32,
+    /// Keeps the uploaded files next to the encoded ones, so they can be encoded again at another bitrate.
+    pub keep_originals: bool,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            songs_dir: PathBuf::from("songs"),
+            max_upload_bytes: 2 * 1024 * 1024,
+            max_sounds_per_guild: 100,
+            max_bytes_per_guild: 50 * 1024 * 1024,
+            opus_bitrate: 96_000,
+            keep_originals: false,
+        }
+    }
+}
+
+impl StorageSettings {
+    pub fn song_path(&self, file_name: &str) -> PathBuf {
+        self.songs_dir.join(file_name)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackSettings {
+    /// Bits per second the sounds are compressed to in memory.
+    pub bitrate: i32,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self { bitrate: 193_000 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceSettings {
+    /// `AUTO_LEAVE_GRACE_SECS`
+    pub auto_leave_grace_secs: u64,
+    /// `MAX_SESSION_SECS`, no limit if not set.
+    pub max_session_secs: Option<u64>,
+    /// `IDLE_TIMEOUT_SECS`, no limit if not set.
+    pub idle_timeout_secs: Option<u64>,
+    /// `IGNORED_USERS`, comma separated.
+    pub ignored_users: Vec<u64>,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            auto_leave_grace_secs: 30,
+            max_session_secs: None,
+            idle_timeout_secs: None,
+            ignored_users: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageModeName {
+    All,
+    Identify,
+}
+
+impl FromStr for LanguageModeName {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(Self::All),
+            "identify" => Ok(Self::Identify),
+            _ => Err("expected all or identify".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecognitionSettings {
+    /// `MAX_UTTERANCE_SECS`
+    pub max_utterance_secs: u64,
+    /// `VAD_THRESHOLD`, 0 disables the detector.
+    pub vad_threshold: f32,
+    /// `VAD_HANGOVER_MS`
+    pub vad_hangover_ms: u64,
+    /// `RECOGNITION_WORKERS`, one per cpu if not set.
+    pub workers: Option<usize>,
+    /// `RECOGNITION_QUEUE`, frames queued per speaker.
+    pub queue: usize,
+    /// `LANGUAGE_MODE`
+    pub language_mode: LanguageModeName,
+    /// `LANGUAGE_IDENTIFY_CONFIDENCE`
+    pub identify_confidence: f32,
+}
+
+impl Default for RecognitionSettings {
+    fn default() -> Self {
+        Self {
+            max_utterance_secs: 15,
+            vad_threshold: 300.0,
+            vad_hangover_ms: 300,
+            workers: None,
+            queue: 50,
+            language_mode: LanguageModeName::All,
+            identify_confidence: 0.9,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// `HTTP_ADDR`
+    pub addr: SocketAddr,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    /// Used when `RUST_LOG` is not set.
+    pub filter: String,
+    /// `LOG_FORMAT`
+    pub format: LogFormat,
+    /// `LOG_TRANSCRIPTS`
+    pub transcripts: bool,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            format: LogFormat::Text,
+            transcripts: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file, applies the environment overrides and validates the result.
+    pub fn load() -> Result<Self> {
+        let path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
+        let mut config: Config = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).with_context(|| format!("Invalid {}", path))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(err) => return Err(err).with_context(|| format!("Could not read {}", path)),
+        };
+        if config.models.is_empty() {
+            config.models = default_models();
+        }
+
+        let mut errors = Vec::new();
+        config.apply_env(&mut errors);
+        config.validate(&mut errors);
+        if !errors.is_empty() {
+            bail!("Invalid configuration:\n- {}", errors.join("\n- "));
+        }
+        Ok(config)
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        override_string(&mut self.discord.token, "DISCORD_TOKEN");
+        override_list(&mut self.discord.owners, "OWNER_ID", errors);
+        override_string(&mut self.database.url, "DATABASE_URL");
+
+        let voice = &mut self.voice;
+        override_value(
+            &mut voice.auto_leave_grace_secs,
+            "AUTO_LEAVE_GRACE_SECS",
+            errors,
+        );
+        override_option(&mut voice.max_session_secs, "MAX_SESSION_SECS", errors);
+        override_option(&mut voice.idle_timeout_secs, "IDLE_TIMEOUT_SECS", errors);
+        override_list(&mut voice.ignored_users, "IGNORED_USERS", errors);
+
+        let recognition = &mut self.recognition;
+        override_value(
+            &mut recognition.max_utterance_secs,
+            "MAX_UTTERANCE_SECS",
+            errors,
+        );
+        override_value(&mut recognition.vad_threshold, "VAD_THRESHOLD", errors);
+        override_value(&mut recognition.vad_hangover_ms, "VAD_HANGOVER_MS", errors);
+        override_option(&mut recognition.workers, "RECOGNITION_WORKERS", errors);
+        override_value(&mut recognition.queue, "RECOGNITION_QUEUE", errors);
+        override_value(&mut recognition.language_mode, "LANGUAGE_MODE", errors);
+        override_value(
+            &mut recognition.identify_confidence,
+            "LANGUAGE_IDENTIFY_CONFIDENCE",
+            errors,
+        );
+
+        override_value(&mut self.http.addr, "HTTP_ADDR", errors);
+        override_value(&mut self.logging.format, "LOG_FORMAT", errors);
+        override_value(&mut self.logging.transcripts, "LOG_TRANSCRIPTS", errors);
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let mut check = |ok: bool, error: &str| {
+            if !ok {
+                errors.push(error.to_string());
+            }
+        };
+
+        check(
+            !self.discord.token.is_empty(),
+            "discord.token (DISCORD_TOKEN) must be set",
+        );
+        check(
+            !self.discord.owners.is_empty(),
+            "discord.owners (OWNER_ID) needs at least one owner",
+        );
+        check(
+            !self.discord.prefix.is_empty(),
+            "discord.prefix can't be empty",
+        );
+        check(
+            !self.database.url.is_empty(),
+            "database.url (DATABASE_URL) must be set",
+        );
+
+        for (index, model) in self.models.iter().enumerate() {
+            check(
+                fs::metadata(&model.path).is_ok_and(|metadata| metadata.is_dir()),
+                &format!("models.path {} is not a directory", model.path),
+            );
+            check(
+                !self.models[..index]
+                    .iter()
+                    .any(|other| other.language == model.language),
+                &format!("models has more than one {} model", model.language.to_str()),
+            );
+        }
+
+        check(
+            self.storage.songs_dir.is_dir(),
+            &format!(
+                "storage.songs_dir {} is not a directory",
+                self.storage.songs_dir.display()
+            ),
+        );
+        check(
+            self.storage.max_upload_bytes > 0,
+            "storage.max_upload_bytes must be more than 0",
+        );
+        check(
+            self.storage.max_sounds_per_guild > 0,
+            "storage.max_sounds_per_guild must be more than 0",
+        );
+        check(
+            self.storage.max_bytes_per_guild >= self.storage.max_upload_bytes,
+            "storage.max_bytes_per_guild can't be less than storage.max_upload_bytes",
+        );
+        // Opus only supports bitrates in this range.
+        check(
+            (500..=512_000).contains(&self.playback.bitrate),
+            "playback.bitrate must be between 500 and 512000",
+        );
+        check(
+            (500..=512_000).contains(&self.storage.opus_bitrate),
+            "storage.opus_bitrate must be between 500 and 512000",
+        );
+
+        let recognition = &self.recognition;
+        check(
+            recognition.max_utterance_secs > 0,
+            "recognition.max_utterance_secs must be more than 0",
+        );
+        check(
+            recognition.vad_threshold >= 0.0,
+            "recognition.vad_threshold can't be negative",
+        );
+        check(
+            recognition.workers != Some(0),
+            "recognition.workers must be more than 0",
+        );
+        check(
+            recognition.queue > 0,
+            "recognition.queue must be more than 0",
+        );
+        check(
+            (0.0..=1.0).contains(&recognition.identify_confidence),
+            "recognition.identify_confidence must be between 0 and 1",
+        );
+    }
+}
+
+impl VoiceSettings {
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.auto_leave_grace_secs)
+    }
+}
+
+fn default_models() -> Vec<ModelSettings> {
+    [
+        (ModelLanguage::TURKISH, "vosk/model/turkish"),
+        (ModelLanguage::DUTCH, "vosk/model/dutch"),
+        (ModelLanguage::ENGLISH, "vosk/model/english"),
+    ]
+    .into_iter()
+    .map(|(language, path)| ModelSettings {
+        language,
+        path: path.to_string(),
+    })
+    .collect()
+}
+
+fn override_string(target: &mut String, key: &str) {
+    if let Ok(value) = env::var(key) {
+        *target = value;
+    }
+}
+
+fn override_value<T>(target: &mut T, key: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env::var(key) {
+        match value.trim().parse() {
+            Ok(value) => *target = value,
+            Err(err) => errors.push(format!("{} has an invalid value {:?}: {}", key, value, err)),
+        }
+    }
+}
+
+fn override_option<T>(target: &mut Option<T>, key: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env::var(key) {
+        match value.trim().parse() {
+            Ok(value) => *target = Some(value),
+            Err(err) => errors.push(format!("{} has an invalid value {:?}: {}", key, value, err)),
+        }
+    }
+}
+
+/// Comma separated values, empty entries are skipped.
+fn override_list<T>(target: &mut Vec<T>, key: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let Ok(value) = env::var(key) else {
+        return;
+    };
+    let parsed: std::result::Result<Vec<T>, _> = value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.parse())
+        .collect();
+    match parsed {
+        Ok(parsed) => *target = parsed,
+        Err(err) => errors.push(format!("{} has an invalid value {:?}: {}", key, value, err)),
+    }
+}
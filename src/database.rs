@@ -2,10 +2,76 @@ use sqlx::{PgPool, Pool};
 
 use anyhow::Result;
 
+use crate::metrics;
+
 pub struct DbSound {
     pub prompt: String,
     pub language: String,
     pub file_name: String,
+    /// Set if the sound was installed from the catalog.
+    pub catalog_id: Option<i32>,
+}
+
+/// What publishing a sound needs to know about it.
+pub struct DbSoundFileInfo {
+    pub file_name: String,
+    pub size: i64,
+    pub content_hash: Option<String>,
+}
+
+pub struct DbCatalogSound {
+    pub id: i32,
+    pub prompt: String,
+    pub language: String,
+    pub file_name: String,
+    pub content_hash: String,
+    pub size: i64,
+    pub publisher_id: String,
+    pub server_id: String,
+    pub installs: i32,
+}
+
+/// Outcome of [`Database::publish_sound`].
+pub enum Published {
+    /// The id of the new catalog entry.
+    Added(i32),
+    /// The same sound is already in the catalog.
+    AlreadyPublished,
+    /// The blob of the sound was released in the meantime.
+    MissingBlob,
+}
+
+/// A blob that isn't encoded at the configured bitrate.
+pub struct DbBlobEncoding {
+    pub content_hash: String,
+    pub bitrate: Option<i32>,
+}
+
+pub struct DbSoundFile {
+    pub server_id: String,
+    pub file_name: String,
+}
+
+pub struct DbSoundUsage {
+    pub sounds: i64,
+    pub bytes: i64,
+}
+
+/// Limits an owner set for a guild, the missing ones fall back to the configured defaults.
+pub struct DbGuildQuota {
+    pub max_sounds: Option<i32>,
+    pub max_bytes: Option<i64>,
+}
+
+pub struct DbVoiceSession {
+    pub server_id: String,
+    pub channel_id: String,
+}
+
+pub struct DbUserLanguage {
+    pub server_id: String,
+    pub user_id: String,
+    pub language: String,
 }
 
 pub struct Database {
@@ -13,23 +79,34 @@ pub struct Database {
 }
 
 impl Database {
-    pub async fn new() -> Result<Self> {
-        let pool = Pool::connect(
-            std::env::var("DATABASE_URL")
-                .expect("DATABASE_URL must be set")
-                .as_str(),
-        )
-        .await
-        .map_err(anyhow::Error::from)?;
+    pub async fn new(url: &str) -> Result<Self> {
+        let pool = Pool::connect(url).await.map_err(anyhow::Error::from)?;
 
         tracing::info!("Connected to database");
         Ok(Self { pool })
     }
 
+    /// Waits for the running queries and closes the connections.
+    pub async fn close(&self) {
+        self.pool.close().await;
+        tracing::info!("Closed the database connections");
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        let _timer = metrics::db_timer("ping");
+        sqlx::query_scalar!(r#"SELECT 1"#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
     pub async fn get_sounds(&self, server_id: &str) -> Result<Vec<DbSound>> {
+        let _timer = metrics::db_timer("get_sounds");
         sqlx::query_as!(
             DbSound,
-            r#"SELECT prompt, language, file_name FROM sounds WHERE server_id = $1"#,
+            r#"SELECT prompt, language, file_name, catalog_id FROM sounds WHERE server_id = $1"#,
             server_id,
         )
         .fetch_all(&self.pool)
@@ -37,36 +114,727 @@ impl Database {
         .map_err(anyhow::Error::from)
     }
 
+    /// Prompts of the server that contain `partial`, used for autocompletion.
+    /// Discord doesn't show more than 25 choices so the result is capped at that.
+    pub async fn search_prompts(&self, server_id: &str, partial: &str) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("search_prompts");
+        sqlx::query_scalar!(
+            r#"SELECT prompt FROM sounds WHERE server_id = $1 AND position(lower($2) in lower(prompt)) > 0 ORDER BY prompt LIMIT 25"#,
+            server_id,
+            partial,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Returns false if the guild already has a sound with the prompt and language.
     pub async fn add_sound(
         &self,
         server_id: &str,
         prompt: &str,
         language: &str,
-        file_name: &str,
-    ) -> Result<()> {
-        sqlx::query!(
-            r#"INSERT INTO sounds (server_id, prompt, language, file_name) VALUES ($1, $2, $3, $4)"#,
+        content_hash: &str,
+        size: i64,
+    ) -> Result<bool> {
+        let _timer = metrics::db_timer("add_sound");
+        let result = sqlx::query!(
+            r#"INSERT INTO sounds (server_id, prompt, language, file_name, content_hash, size) VALUES ($1, $2, $3, $4, $4, $5)
+            ON CONFLICT (server_id, prompt, language) DO NOTHING"#,
             server_id,
             prompt,
             language,
-            file_name,
+            content_hash,
+            size,
         )
         .execute(&self.pool)
         .await
         .map_err(anyhow::Error::from)?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn remove_sound(&self, server_id: &str, prompt: &str) -> Result<DbSound> {
+    /// Removes the sound in the language, or in every language if it's `None`. Returns the removed sounds.
+    pub async fn remove_sound(
+        &self,
+        server_id: &str,
+        prompt: &str,
+        language: Option<&str>,
+    ) -> Result<Vec<DbSound>> {
+        let _timer = metrics::db_timer("remove_sound");
         sqlx::query_as!(
             DbSound,
-            r#"DELETE FROM sounds WHERE server_id = $1 AND prompt = $2 returning prompt, language, file_name"#,
+            r#"DELETE FROM sounds WHERE server_id = $1 AND prompt = $2 AND ($3::TEXT IS NULL OR language = $3)
+            returning prompt, language, file_name, catalog_id"#,
             server_id,
             prompt,
+            language,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn get_sound_file_info(
+        &self,
+        server_id: &str,
+        prompt: &str,
+        language: &str,
+    ) -> Result<Option<DbSoundFileInfo>> {
+        let _timer = metrics::db_timer("get_sound_file_info");
+        sqlx::query_as!(
+            DbSoundFileInfo,
+            r#"SELECT file_name, size, content_hash FROM sounds WHERE server_id = $1 AND prompt = $2 AND language = $3 LIMIT 1"#,
+            server_id,
+            prompt,
+            language,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Files of every sound in every server.
+    pub async fn get_sound_files(&self) -> Result<Vec<DbSoundFile>> {
+        let _timer = metrics::db_timer("get_sound_files");
+        sqlx::query_as!(DbSoundFile, r#"SELECT server_id, file_name FROM sounds"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    pub async fn get_sound_usage(&self, server_id: &str) -> Result<DbSoundUsage> {
+        let _timer = metrics::db_timer("get_sound_usage");
+        sqlx::query_as!(
+            DbSoundUsage,
+            r#"SELECT COUNT(*) AS "sounds!", COALESCE(SUM(size), 0)::BIGINT AS "bytes!" FROM sounds WHERE server_id = $1"#,
+            server_id,
         )
         .fetch_one(&self.pool)
         .await
         .map_err(anyhow::Error::from)
     }
+
+    /// Files of the sounds that were added before sizes were recorded.
+    pub async fn get_unsized_sound_files(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_unsized_sound_files");
+        sqlx::query_scalar!(r#"SELECT file_name FROM sounds WHERE size = 0"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    pub async fn set_sound_size(&self, file_name: &str, size: i64) -> Result<()> {
+        let _timer = metrics::db_timer("set_sound_size");
+        sqlx::query!(
+            r#"UPDATE sounds SET size = $2 WHERE file_name = $1"#,
+            file_name,
+            size,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn get_guild_quota(&self, server_id: &str) -> Result<Option<DbGuildQuota>> {
+        let _timer = metrics::db_timer("get_guild_quota");
+        sqlx::query_as!(
+            DbGuildQuota,
+            r#"SELECT max_sounds, max_bytes FROM guild_quotas WHERE server_id = $1"#,
+            server_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn set_guild_quota(
+        &self,
+        server_id: &str,
+        max_sounds: Option<i32>,
+        max_bytes: Option<i64>,
+    ) -> Result<()> {
+        let _timer = metrics::db_timer("set_guild_quota");
+        sqlx::query!(
+            r#"INSERT INTO guild_quotas (server_id, max_sounds, max_bytes) VALUES ($1, $2, $3)
+            ON CONFLICT (server_id) DO UPDATE SET max_sounds = $2, max_bytes = $3, created_at = CURRENT_TIMESTAMP"#,
+            server_id,
+            max_sounds,
+            max_bytes,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Returns false if the guild didn't have its own quota.
+    pub async fn remove_guild_quota(&self, server_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("remove_guild_quota");
+        let result = sqlx::query!(
+            r#"DELETE FROM guild_quotas WHERE server_id = $1"#,
+            server_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_opted_out_users(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_opted_out_users");
+        sqlx::query_scalar!(r#"SELECT user_id FROM opted_out_users"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    pub async fn opt_out(&self, user_id: &str) -> Result<()> {
+        let _timer = metrics::db_timer("opt_out");
+        sqlx::query!(
+            r#"INSERT INTO opted_out_users (user_id) VALUES ($1) ON CONFLICT DO NOTHING"#,
+            user_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn opt_in(&self, user_id: &str) -> Result<()> {
+        let _timer = metrics::db_timer("opt_in");
+        sqlx::query!(r#"DELETE FROM opted_out_users WHERE user_id = $1"#, user_id,)
+            .execute(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn get_user_languages(&self) -> Result<Vec<DbUserLanguage>> {
+        let _timer = metrics::db_timer("get_user_languages");
+        sqlx::query_as!(
+            DbUserLanguage,
+            r#"SELECT server_id, user_id, language FROM user_languages"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn set_user_language(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        language: &str,
+    ) -> Result<()> {
+        let _timer = metrics::db_timer("set_user_language");
+        sqlx::query!(
+            r#"INSERT INTO user_languages (server_id, user_id, language) VALUES ($1, $2, $3)
+            ON CONFLICT (server_id, user_id) DO UPDATE SET language = $3, created_at = CURRENT_TIMESTAMP"#,
+            server_id,
+            user_id,
+            language,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Returns false if the user didn't set a language.
+    pub async fn clear_user_language(&self, server_id: &str, user_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("clear_user_language");
+        let result = sqlx::query!(
+            r#"DELETE FROM user_languages WHERE server_id = $1 AND user_id = $2"#,
+            server_id,
+            user_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_listen_roles(&self, server_id: &str) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_listen_roles");
+        sqlx::query_scalar!(
+            r#"SELECT role_id FROM listen_roles WHERE server_id = $1"#,
+            server_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Returns false if the role was already in the list.
+    pub async fn add_listen_role(&self, server_id: &str, role_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("add_listen_role");
+        let result = sqlx::query!(
+            r#"INSERT INTO listen_roles (server_id, role_id) VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            server_id,
+            role_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if the role wasn't in the list.
+    pub async fn remove_listen_role(&self, server_id: &str, role_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("remove_listen_role");
+        let result = sqlx::query!(
+            r#"DELETE FROM listen_roles WHERE server_id = $1 AND role_id = $2"#,
+            server_id,
+            role_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_auto_join_channels(&self, server_id: &str) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_auto_join_channels");
+        sqlx::query_scalar!(
+            r#"SELECT channel_id FROM auto_join_channels WHERE server_id = $1"#,
+            server_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Returns false if the channel was already in the list.
+    pub async fn add_auto_join_channel(&self, server_id: &str, channel_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("add_auto_join_channel");
+        let result = sqlx::query!(
+            r#"INSERT INTO auto_join_channels (server_id, channel_id) VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            server_id,
+            channel_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if the channel wasn't in the list.
+    pub async fn remove_auto_join_channel(
+        &self,
+        server_id: &str,
+        channel_id: &str,
+    ) -> Result<bool> {
+        let _timer = metrics::db_timer("remove_auto_join_channel");
+        let result = sqlx::query!(
+            r#"DELETE FROM auto_join_channels WHERE server_id = $1 AND channel_id = $2"#,
+            server_id,
+            channel_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_voice_sessions(&self) -> Result<Vec<DbVoiceSession>> {
+        let _timer = metrics::db_timer("get_voice_sessions");
+        sqlx::query_as!(
+            DbVoiceSession,
+            r#"SELECT server_id, channel_id FROM voice_sessions"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn save_voice_session(&self, server_id: &str, channel_id: &str) -> Result<()> {
+        let _timer = metrics::db_timer("save_voice_session");
+        sqlx::query!(
+            r#"INSERT INTO voice_sessions (server_id, channel_id) VALUES ($1, $2)
+            ON CONFLICT (server_id) DO UPDATE SET channel_id = $2, created_at = CURRENT_TIMESTAMP"#,
+            server_id,
+            channel_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn remove_voice_session(&self, server_id: &str) -> Result<()> {
+        let _timer = metrics::db_timer("remove_voice_session");
+        sqlx::query!(
+            r#"DELETE FROM voice_sessions WHERE server_id = $1"#,
+            server_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn get_blocked_guilds(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_blocked_guilds");
+        sqlx::query_scalar!(r#"SELECT server_id FROM blocked_guilds"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Returns false if the guild was already blocked.
+    pub async fn block_guild(&self, server_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("block_guild");
+        let result = sqlx::query!(
+            r#"INSERT INTO blocked_guilds (server_id) VALUES ($1) ON CONFLICT DO NOTHING"#,
+            server_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if the guild wasn't blocked.
+    pub async fn unblock_guild(&self, server_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("unblock_guild");
+        let result = sqlx::query!(
+            r#"DELETE FROM blocked_guilds WHERE server_id = $1"#,
+            server_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_blocked_users(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_blocked_users");
+        sqlx::query_scalar!(r#"SELECT user_id FROM blocked_users"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Returns false if the user was already blocked.
+    pub async fn block_user(&self, user_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("block_user");
+        let result = sqlx::query!(
+            r#"INSERT INTO blocked_users (user_id) VALUES ($1) ON CONFLICT DO NOTHING"#,
+            user_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns false if the user wasn't blocked.
+    pub async fn unblock_user(&self, user_id: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("unblock_user");
+        let result = sqlx::query!(r#"DELETE FROM blocked_users WHERE user_id = $1"#, user_id,)
+            .execute(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Adds the sound to the catalog and takes a reference to its blob, in one transaction.
+    /// Has to be called with the `BlobStore` lock held.
+    pub async fn publish_sound(
+        &self,
+        sound: &DbSoundFileInfo,
+        content_hash: &str,
+        prompt: &str,
+        language: &str,
+        publisher_id: &str,
+        server_id: &str,
+    ) -> Result<Published> {
+        let _timer = metrics::db_timer("publish_sound");
+        let mut transaction = self.pool.begin().await?;
+        let refs = sqlx::query_scalar!(
+            r#"UPDATE blobs SET refs = refs + 1 WHERE content_hash = $1 RETURNING refs"#,
+            content_hash,
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if refs.is_none() {
+            return Ok(Published::MissingBlob);
+        }
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO catalog_sounds (prompt, language, file_name, content_hash, size, publisher_id, server_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING RETURNING id"#,
+            prompt,
+            language,
+            sound.file_name,
+            content_hash,
+            sound.size,
+            publisher_id,
+            server_id,
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(id) = id else {
+            // Rolls back the reference.
+            return Ok(Published::AlreadyPublished);
+        };
+        transaction.commit().await?;
+
+        Ok(Published::Added(id))
+    }
+
+    /// Catalog sounds whose prompt contains `query`, the most installed first.
+    pub async fn search_catalog(
+        &self,
+        query: &str,
+        language: Option<&str>,
+    ) -> Result<Vec<DbCatalogSound>> {
+        let _timer = metrics::db_timer("search_catalog");
+        sqlx::query_as!(
+            DbCatalogSound,
+            r#"SELECT id, prompt, language, file_name, content_hash, size, publisher_id, server_id, installs
+            FROM catalog_sounds
+            WHERE position(lower($1) in lower(prompt)) > 0 AND ($2::VARCHAR IS NULL OR language = $2)
+            ORDER BY installs DESC, id LIMIT 20"#,
+            query,
+            language,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    pub async fn get_catalog_sound(&self, id: i32) -> Result<Option<DbCatalogSound>> {
+        let _timer = metrics::db_timer("get_catalog_sound");
+        sqlx::query_as!(
+            DbCatalogSound,
+            r#"SELECT id, prompt, language, file_name, content_hash, size, publisher_id, server_id, installs
+            FROM catalog_sounds WHERE id = $1"#,
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Adds the catalog sound to the server, sharing its file.
+    /// Adds the catalog sound to the guild and takes a reference to its blob, in one transaction.
+    /// Returns false if the blob was released in the meantime.
+    /// Has to be called with the `BlobStore` lock held.
+    pub async fn install_catalog_sound(
+        &self,
+        server_id: &str,
+        sound: &DbCatalogSound,
+    ) -> Result<bool> {
+        let _timer = metrics::db_timer("install_catalog_sound");
+        let mut transaction = self.pool.begin().await?;
+        let refs = sqlx::query_scalar!(
+            r#"UPDATE blobs SET refs = refs + 1 WHERE content_hash = $1 RETURNING refs"#,
+            sound.content_hash,
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if refs.is_none() {
+            return Ok(false);
+        }
+        sqlx::query!(
+            r#"INSERT INTO sounds (server_id, prompt, language, file_name, size, content_hash, catalog_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            server_id,
+            sound.prompt,
+            sound.language,
+            sound.file_name,
+            sound.size,
+            sound.content_hash,
+            sound.id,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"UPDATE catalog_sounds SET installs = installs + 1 WHERE id = $1"#,
+            sound.id,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Installed copies of the sound stay in their servers.
+    pub async fn unpublish_sound(&self, id: i32) -> Result<()> {
+        let _timer = metrics::db_timer("unpublish_sound");
+        sqlx::query!(r#"DELETE FROM catalog_sounds WHERE id = $1"#, id,)
+            .execute(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    pub async fn get_catalog_file_names(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_catalog_file_names");
+        sqlx::query_scalar!(r#"SELECT DISTINCT file_name FROM catalog_sounds"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Adds a reference to the blob, creating it if it's new.
+    /// `bitrate` is only recorded for new blobs, see [`Database::set_blob_encoding`].
+    pub async fn acquire_blob(
+        &self,
+        content_hash: &str,
+        size: i64,
+        bitrate: Option<i32>,
+    ) -> Result<()> {
+        let _timer = metrics::db_timer("acquire_blob");
+        sqlx::query!(
+            r#"INSERT INTO blobs (content_hash, size, refs, bitrate) VALUES ($1, $2, 1, $3)
+            ON CONFLICT (content_hash) DO UPDATE SET refs = blobs.refs + 1"#,
+            content_hash,
+            size,
+            bitrate,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Drops a reference to the blob. Returns true if it was the last one and the blob was removed.
+    pub async fn release_blob(&self, content_hash: &str) -> Result<bool> {
+        let _timer = metrics::db_timer("release_blob");
+        let mut transaction = self.pool.begin().await?;
+        let refs = sqlx::query_scalar!(
+            r#"UPDATE blobs SET refs = refs - 1 WHERE content_hash = $1 RETURNING refs"#,
+            content_hash,
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let removed = refs.is_some_and(|refs| refs <= 0);
+        if removed {
+            sqlx::query!(r#"DELETE FROM blobs WHERE content_hash = $1"#, content_hash,)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(removed)
+    }
+
+    /// Files of sounds and catalog entries that are not stored as blobs yet.
+    pub async fn get_legacy_files(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("get_legacy_files");
+        sqlx::query_scalar!(
+            r#"SELECT file_name AS "file_name!" FROM sounds WHERE file_name NOT IN (SELECT content_hash FROM blobs)
+            UNION SELECT file_name FROM catalog_sounds WHERE file_name NOT IN (SELECT content_hash FROM blobs)"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
+
+    /// Points the sounds and catalog entries of a legacy file to its blob.
+    pub async fn move_to_blob(&self, file_name: &str, content_hash: &str, size: i64) -> Result<()> {
+        let _timer = metrics::db_timer("move_to_blob");
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO blobs (content_hash, size) VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            content_hash,
+            size,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"UPDATE sounds SET file_name = $2, content_hash = $2 WHERE file_name = $1"#,
+            file_name,
+            content_hash,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"UPDATE catalog_sounds SET file_name = $2, content_hash = $2 WHERE file_name = $1"#,
+            file_name,
+            content_hash,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Recounts the references of every blob, in case a crash left them out of sync,
+    /// and removes the blobs nothing refers to. Returns the removed ones.
+    pub async fn recount_blob_refs(&self) -> Result<Vec<String>> {
+        let _timer = metrics::db_timer("recount_blob_refs");
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query!(
+            r#"UPDATE blobs SET refs =
+                (SELECT COUNT(*) FROM sounds WHERE sounds.file_name = blobs.content_hash)
+                + (SELECT COUNT(*) FROM catalog_sounds WHERE catalog_sounds.file_name = blobs.content_hash)"#
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let removed =
+            sqlx::query_scalar!(r#"DELETE FROM blobs WHERE refs = 0 RETURNING content_hash"#)
+                .fetch_all(&mut *transaction)
+                .await?;
+        transaction.commit().await?;
+
+        Ok(removed)
+    }
+
+    /// Records the size and bitrate of a blob after it was (re-)encoded.
+    /// Sounds keep their uploaded size, that is what counts against the quota.
+    pub async fn set_blob_encoding(
+        &self,
+        content_hash: &str,
+        size: i64,
+        bitrate: i32,
+    ) -> Result<()> {
+        let _timer = metrics::db_timer("set_blob_encoding");
+        sqlx::query!(
+            r#"UPDATE blobs SET size = $2, bitrate = $3 WHERE content_hash = $1"#,
+            content_hash,
+            size,
+            bitrate,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_blobs_not_at_bitrate(&self, bitrate: i32) -> Result<Vec<DbBlobEncoding>> {
+        let _timer = metrics::db_timer("get_blobs_not_at_bitrate");
+        sqlx::query_as!(
+            DbBlobEncoding,
+            r#"SELECT content_hash, bitrate FROM blobs WHERE bitrate IS DISTINCT FROM $1"#,
+            bitrate,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(anyhow::Error::from)
+    }
 }
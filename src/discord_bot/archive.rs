@@ -0,0 +1,252 @@
+//! Portable soundboard archives used by `/export` and `/import`.
+//!
+//! An archive is a zip with a `manifest.json` and the audio files under `sounds/`.
+
+use std::io::{Cursor, Read, Write};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+const MANIFEST_NAME: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+/// Thousands of sounds fit in it, a bigger manifest is a zip bomb.
+const MAX_MANIFEST_BYTES: u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Guild the archive was exported from. The settings are only imported back into it,
+    /// role and channel ids mean nothing in other guilds.
+    pub guild_id: String,
+    pub sounds: Vec<ManifestSound>,
+    pub settings: GuildSettings,
+}
+
+impl Manifest {
+    pub fn new(guild_id: String, sounds: Vec<ManifestSound>, settings: GuildSettings) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            guild_id,
+            sounds,
+            settings,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestSound {
+    pub prompt: String,
+    pub language: String,
+    /// Path of the audio file in the archive.
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GuildSettings {
+    pub listen_roles: Vec<String>,
+    pub auto_join_channels: Vec<String>,
+}
+
+/// A sound read from an archive, with its audio or why it couldn't be read.
+pub struct ArchivedSound {
+    pub prompt: String,
+    pub language: String,
+    pub content: Result<Vec<u8>, String>,
+}
+
+/// Zips the manifest and the audio files, keyed by their path in the manifest.
+pub fn write(manifest: &Manifest, files: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(
+        MANIFEST_NAME,
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+
+    // Audio is already compressed, deflating it again only costs time.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (path, content) in files {
+        zip.start_file(path.as_str(), stored)?;
+        zip.write_all(content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Reads the manifest and every sound in it. Files bigger than `max_file_bytes` are not decompressed,
+/// and archives with more than `max_sounds` sounds are rejected.
+pub fn read(
+    archive: Vec<u8>,
+    max_file_bytes: u64,
+    max_sounds: usize,
+) -> Result<(Manifest, Vec<ArchivedSound>)> {
+    let mut zip = ZipArchive::new(Cursor::new(archive)).context("Not a zip archive")?;
+    let manifest: Manifest = {
+        let file = zip
+            .by_name(MANIFEST_NAME)
+            .context("The archive has no manifest.json")?;
+        let mut content = Vec::new();
+        file.take(MAX_MANIFEST_BYTES + 1)
+            .read_to_end(&mut content)
+            .context("Could not read manifest.json")?;
+        if content.len() as u64 > MAX_MANIFEST_BYTES {
+            bail!("manifest.json is too large");
+        }
+        serde_json::from_slice(&content).context("Invalid manifest.json")?
+    };
+    if manifest.version != MANIFEST_VERSION {
+        bail!("Unsupported archive version {}", manifest.version);
+    }
+    if manifest.sounds.len() > max_sounds {
+        bail!(
+            "The archive has {} sounds, this server can only have {}",
+            manifest.sounds.len(),
+            max_sounds
+        );
+    }
+
+    let sounds = manifest
+        .sounds
+        .iter()
+        .map(|sound| ArchivedSound {
+            prompt: sound.prompt.clone(),
+            language: sound.language.clone(),
+            content: read_file(&mut zip, &sound.file, max_file_bytes),
+        })
+        .collect();
+    Ok((manifest, sounds))
+}
+
+fn read_file(
+    zip: &mut ZipArchive<Cursor<Vec<u8>>>,
+    path: &str,
+    max_file_bytes: u64,
+) -> Result<Vec<u8>, String> {
+    let file = zip
+        .by_name(path)
+        .map_err(|_| format!("{} is missing from the archive", path))?;
+    // The declared size can lie, so the read is capped as well.
+    if file.size() > max_file_bytes {
+        return Err(format!(
+            "File size too large. Max {}kb.",
+            max_file_bytes / 1024
+        ));
+    }
+    let mut content = Vec::new();
+    file.take(max_file_bytes + 1)
+        .read_to_end(&mut content)
+        .map_err(|err| format!("Could not read {}: {}", path, err))?;
+    if content.len() as u64 > max_file_bytes {
+        return Err(format!(
+            "File size too large. Max {}kb.",
+            max_file_bytes / 1024
+        ));
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound(prompt: &str, file: &str) -> ManifestSound {
+        ManifestSound {
+            prompt: prompt.to_string(),
+            language: "english".to_string(),
+            file: file.to_string(),
+        }
+    }
+
+    fn manifest(sounds: Vec<ManifestSound>) -> Manifest {
+        Manifest::new("1".to_string(), sounds, GuildSettings::default())
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = GuildSettings {
+            listen_roles: vec!["2".to_string()],
+            auto_join_channels: vec!["3".to_string()],
+        };
+        let manifest = Manifest::new(
+            "1".to_string(),
+            vec![sound("max", "sounds/a"), sound("lando", "sounds/b")],
+            settings,
+        );
+        let files = vec![
+            ("sounds/a".to_string(), vec![1, 2, 3]),
+            ("sounds/b".to_string(), vec![4; 100]),
+        ];
+        let archive = write(&manifest, &files).unwrap();
+
+        let (read_manifest, sounds) = read(archive, 1024, 10).unwrap();
+        assert_eq!(read_manifest.guild_id, "1");
+        assert_eq!(read_manifest.settings.listen_roles, ["2"]);
+        assert_eq!(read_manifest.settings.auto_join_channels, ["3"]);
+        assert_eq!(sounds.len(), 2);
+        assert_eq!(sounds[0].prompt, "max");
+        assert_eq!(sounds[0].language, "english");
+        assert_eq!(sounds[0].content, Ok(vec![1, 2, 3]));
+        assert_eq!(sounds[1].content, Ok(vec![4; 100]));
+    }
+
+    #[test]
+    fn missing_file_is_reported_per_sound() {
+        let manifest = manifest(vec![sound("max", "sounds/a"), sound("lando", "sounds/b")]);
+        let archive = write(&manifest, &[("sounds/a".to_string(), vec![1])]).unwrap();
+
+        let (_, sounds) = read(archive, 1024, 10).unwrap();
+        assert_eq!(sounds[0].content, Ok(vec![1]));
+        assert_eq!(
+            sounds[1].content,
+            Err("sounds/b is missing from the archive".to_string())
+        );
+    }
+
+    #[test]
+    fn oversized_file_is_not_read() {
+        let manifest = manifest(vec![sound("max", "sounds/a"), sound("lando", "sounds/b")]);
+        let files = vec![
+            ("sounds/a".to_string(), vec![0; 2048]),
+            ("sounds/b".to_string(), vec![0; 1024]),
+        ];
+        let archive = write(&manifest, &files).unwrap();
+
+        let (_, sounds) = read(archive, 1024, 10).unwrap();
+        assert!(sounds[0]
+            .content
+            .as_ref()
+            .unwrap_err()
+            .contains("too large"));
+        assert_eq!(sounds[1].content.as_ref().unwrap().len(), 1024);
+    }
+
+    #[test]
+    fn invalid_archives_are_rejected() {
+        let mut manifest = manifest(vec![sound("max", "sounds/a")]);
+        let files = vec![("sounds/a".to_string(), vec![1])];
+
+        let archive = write(&manifest, &files).unwrap();
+        let err = read(archive, 1024, 0).err().unwrap();
+        assert!(err.to_string().contains("can only have 0"));
+
+        manifest.version = MANIFEST_VERSION + 1;
+        let archive = write(&manifest, &files).unwrap();
+        let err = read(archive, 1024, 10).err().unwrap();
+        assert!(err.to_string().contains("Unsupported archive version"));
+
+        assert!(read(b"not a zip".to_vec(), 1024, 10).is_err());
+    }
+
+    #[test]
+    fn oversized_manifest_is_rejected() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(MANIFEST_NAME, deflated).unwrap();
+        zip.write_all(&vec![b' '; MAX_MANIFEST_BYTES as usize + 1])
+            .unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        let err = read(archive, 1024, 10).err().unwrap();
+        assert!(err.to_string().contains("too large"));
+    }
+}
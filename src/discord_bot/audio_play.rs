@@ -1,45 +1,100 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
-use serenity::all::GuildId;
+use serenity::{all::GuildId, async_trait};
 use songbird::{
     driver::Bitrate,
-    input::{cached::Compressed, File},
-    Songbird,
+    events::EventHandler as VoiceEventHandler,
+    input::{cached::Compressed, Input},
+    Event, EventContext, Songbird, TrackEvent,
 };
 
-use crate::speech_to_text::ModelLanguage;
+use crate::{metrics, speech_to_text::ModelLanguage};
+
+use super::transcode;
+
+/// A sound kept in memory, ready to be played.
+pub enum Song {
+    /// Already Opus, played as it is.
+    Opus(Arc<[u8]>),
+    /// Stored as it was uploaded, compressed when it's loaded.
+    Compressed(Compressed),
+}
+
+impl Song {
+    fn input(&self) -> Input {
+        match self {
+            Song::Opus(content) => content.clone().into(),
+            Song::Compressed(compressed) => compressed.new_handle().into(),
+        }
+    }
+}
 
 pub struct SongPlayer {
-    pub songs: HashMap<(String, ModelLanguage), Compressed>,
+    pub songs: HashMap<(String, ModelLanguage), Song>,
     pub client: Arc<Songbird>,
     pub guild_id: GuildId,
+    /// Bits per second the sounds that aren't stored as Opus are compressed to.
+    pub bitrate: i32,
 }
 impl SongPlayer {
-    pub fn new(client: Arc<Songbird>, guild_id: GuildId) -> Self {
+    pub fn new(client: Arc<Songbird>, guild_id: GuildId, bitrate: i32) -> Self {
         Self {
             songs: HashMap::new(),
             client,
             guild_id,
+            bitrate,
         }
     }
-    pub async fn add_song(&mut self, name: &str, model_language: ModelLanguage, song_path: &str) {
-        let src = Compressed::new(
-            File::new(song_path.to_string()).into(),
-            Bitrate::BitsPerSecond(193_000),
-        )
-        .await
-        .expect("These parameters are well-defined.");
-        let loader_handler = src.raw.spawn_loader();
-        self.songs.insert((name.to_string(), model_language), src);
-        let _ = loader_handler.join();
+    pub async fn add_song(
+        &mut self,
+        name: &str,
+        model_language: ModelLanguage,
+        song_path: PathBuf,
+    ) {
+        let content = match tokio::fs::read(&song_path).await {
+            Ok(content) => content,
+            Err(err) => {
+                tracing::error!("Could not load {}: {:?}", song_path.display(), err);
+                return;
+            }
+        };
+        let song = if transcode::is_opus(&content) {
+            Song::Opus(content.into())
+        } else {
+            let src = Compressed::new(content.into(), Bitrate::BitsPerSecond(self.bitrate))
+                .await
+                .expect("These parameters are well-defined.");
+            let loader_handler = src.raw.spawn_loader();
+            let _ = loader_handler.join();
+            Song::Compressed(src)
+        };
+        self.songs.insert((name.to_string(), model_language), song);
     }
 
     pub async fn play_song(&self, name: &str, model_language: ModelLanguage) {
         if let Some(source) = self.songs.get(&(name.to_string(), model_language)) {
             if let Some(songbird_handler_lock) = self.client.get(self.guild_id) {
                 let mut songbird_handler = songbird_handler_lock.lock().await;
-                let _sound = songbird_handler.play_input(source.new_handle().into());
+                let sound = songbird_handler.play_input(source.input());
+                if let Err(err) = sound.add_event(Event::Track(TrackEvent::Error), PlaybackError) {
+                    tracing::error!("Failed to watch the playback for errors: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+struct PlaybackError;
+
+#[async_trait]
+impl VoiceEventHandler for PlaybackError {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            for (state, _) in tracks.iter() {
+                tracing::error!("Failed to play a sound: {:?}", state.playing);
             }
         }
+        metrics::PLAYBACK_ERRORS.inc();
+        None
     }
 }
@@ -0,0 +1,318 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use tokio::{fs, sync::Mutex};
+
+use crate::{
+    config::StorageSettings,
+    database::{Database, DbBlobEncoding, DbCatalogSound, DbSoundFileInfo, Published},
+};
+
+use super::transcode;
+
+/// Sound files stored under the sha-256 of their uploaded content.
+///
+/// Identical uploads share a single file. The `blobs` table counts the sounds and catalog entries
+/// that refer to each file, and a file is only deleted when its last reference goes away.
+///
+/// Uploads are stored encoded to Opus by [`transcode::to_opus`], files that already are Opus are stored as they are.
+/// With `keep_originals` the uploaded file is kept under `originals/` with the same name.
+///
+/// Encoding runs without the lock, files are staged under a temporary name and moved into place under it.
+pub struct BlobStore {
+    database: Arc<Database>,
+    songs_dir: PathBuf,
+    originals_dir: PathBuf,
+    opus_bitrate: i32,
+    keep_originals: bool,
+    /// Keeps a file from being deleted while another upload of the same content is reusing it.
+    lock: Mutex<()>,
+    /// Makes the names of the staged files unique.
+    staged: AtomicU64,
+}
+
+/// Files written next to their final place, moved there once they are complete.
+struct Staged {
+    stored: PathBuf,
+    original: Option<PathBuf>,
+    /// `None` if the upload was already Opus and is stored as it is.
+    bitrate: Option<i32>,
+}
+
+/// Hex encoded sha-256 of the uploaded file, also its name in the storage.
+pub fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+impl BlobStore {
+    pub fn new(database: Arc<Database>, storage: &StorageSettings) -> Self {
+        Self {
+            database,
+            songs_dir: storage.songs_dir.clone(),
+            originals_dir: storage.songs_dir.join("originals"),
+            opus_bitrate: storage.opus_bitrate,
+            keep_originals: storage.keep_originals,
+            lock: Mutex::new(()),
+            staged: AtomicU64::new(0),
+        }
+    }
+
+    /// Stores the content if it's new and takes a reference to it. Returns its hash.
+    pub async fn put(&self, content: &[u8]) -> Result<String> {
+        let content_hash = content_hash(content);
+        let path = self.songs_dir.join(&content_hash);
+        let mut staged = None;
+        loop {
+            {
+                let _lock = self.lock.lock().await;
+                if fs::try_exists(&path).await? {
+                    let size = fs::metadata(&path).await?.len() as i64;
+                    self.database
+                        .acquire_blob(&content_hash, size, None)
+                        .await?;
+                    drop(_lock);
+                    // Someone else stored the same content while this one was encoded.
+                    if let Some(staged) = staged {
+                        self.discard(staged).await;
+                    }
+                    return Ok(content_hash);
+                }
+                if let Some(staged) = staged.take() {
+                    let bitrate = staged.bitrate;
+                    self.commit(&content_hash, staged).await?;
+                    let size = fs::metadata(&path).await?.len() as i64;
+                    if let Err(err) = self
+                        .database
+                        .acquire_blob(&content_hash, size, bitrate)
+                        .await
+                    {
+                        let _ = fs::remove_file(&path).await;
+                        let _ = remove_original(&self.originals_dir.join(&content_hash)).await;
+                        return Err(err);
+                    }
+                    return Ok(content_hash);
+                }
+            }
+            staged = Some(self.stage(&content_hash, content).await?);
+        }
+    }
+
+    /// Encodes the content next to its final place, without holding the lock.
+    async fn stage(&self, content_hash: &str, content: &[u8]) -> Result<Staged> {
+        let (encoded, bitrate) = if transcode::is_opus(content) {
+            (None, None)
+        } else {
+            let encoded = transcode::to_opus(content.to_vec(), self.opus_bitrate).await?;
+            (Some(encoded), Some(self.opus_bitrate))
+        };
+        let stored = self.staged_path(&self.songs_dir, content_hash);
+        fs::write(&stored, encoded.as_deref().unwrap_or(content)).await?;
+
+        // An upload that is stored as it is doesn't need a copy.
+        let original = if self.keep_originals && encoded.is_some() {
+            fs::create_dir_all(&self.originals_dir).await?;
+            let original = self.staged_path(&self.originals_dir, content_hash);
+            if let Err(err) = fs::write(&original, content).await {
+                let _ = fs::remove_file(&stored).await;
+                return Err(err.into());
+            }
+            Some(original)
+        } else {
+            None
+        };
+        Ok(Staged {
+            stored,
+            original,
+            bitrate,
+        })
+    }
+
+    fn staged_path(&self, dir: &Path, content_hash: &str) -> PathBuf {
+        let id = self.staged.fetch_add(1, Ordering::Relaxed);
+        dir.join(format!("{}.{}.partial", content_hash, id))
+    }
+
+    /// Moves the staged files into place. Has to be called with the lock held.
+    async fn commit(&self, content_hash: &str, staged: Staged) -> Result<()> {
+        if let Some(original) = &staged.original {
+            fs::rename(original, self.originals_dir.join(content_hash)).await?;
+        }
+        fs::rename(&staged.stored, self.songs_dir.join(content_hash)).await?;
+        Ok(())
+    }
+
+    async fn discard(&self, staged: Staged) {
+        let _ = fs::remove_file(&staged.stored).await;
+        if let Some(original) = &staged.original {
+            let _ = fs::remove_file(original).await;
+        }
+    }
+
+    /// The uploaded file if it was kept, otherwise the stored one.
+    pub async fn read_original(&self, content_hash: &str) -> Result<Vec<u8>> {
+        match fs::read(self.originals_dir.join(content_hash)).await {
+            Ok(content) => Ok(content),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Ok(fs::read(self.songs_dir.join(content_hash)).await?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Publishes the sound to the catalog, which takes another reference to its blob.
+    /// The lock keeps the blob from being released in between.
+    pub async fn publish(
+        &self,
+        sound: &DbSoundFileInfo,
+        content_hash: &str,
+        prompt: &str,
+        language: &str,
+        publisher_id: &str,
+        server_id: &str,
+    ) -> Result<Published> {
+        let _lock = self.lock.lock().await;
+        self.database
+            .publish_sound(
+                sound,
+                content_hash,
+                prompt,
+                language,
+                publisher_id,
+                server_id,
+            )
+            .await
+    }
+
+    /// Installs the catalog sound in the guild, which takes another reference to its blob.
+    /// Returns false if the blob is gone.
+    pub async fn install(&self, server_id: &str, sound: &DbCatalogSound) -> Result<bool> {
+        let _lock = self.lock.lock().await;
+        self.database.install_catalog_sound(server_id, sound).await
+    }
+
+    /// Drops a reference, deleting the file if it was the last one.
+    pub async fn release(&self, content_hash: &str) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        if self.database.release_blob(content_hash).await? {
+            fs::remove_file(self.songs_dir.join(content_hash)).await?;
+            remove_original(&self.originals_dir.join(content_hash)).await?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the blobs that aren't at the configured bitrate, from their original if it was kept.
+    /// Files stored before encoding was added are encoded as well, blobs already encoded at another
+    /// bitrate without an original are left alone.
+    /// Returns how many blobs were encoded, the ones that failed are logged and skipped.
+    pub async fn reencode(&self) -> Result<usize> {
+        let mut encoded = 0;
+        for blob in self
+            .database
+            .get_blobs_not_at_bitrate(self.opus_bitrate)
+            .await?
+        {
+            match self.reencode_blob(&blob).await {
+                Ok(true) => encoded += 1,
+                Ok(false) => {}
+                Err(err) => tracing::warn!("Could not encode {}: {:?}", blob.content_hash, err),
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// Encodes without the lock, the result only replaces the file if the blob still exists.
+    async fn reencode_blob(&self, blob: &DbBlobEncoding) -> Result<bool> {
+        let path = self.songs_dir.join(&blob.content_hash);
+        let original_path = self.originals_dir.join(&blob.content_hash);
+        let (original, kept) = match fs::read(&original_path).await {
+            Ok(content) => (content, true),
+            Err(err) if err.kind() == ErrorKind::NotFound && blob.bitrate.is_none() => {
+                (fs::read(&path).await?, false)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        // Uploads that were already Opus have nothing better to be encoded from.
+        if transcode::is_opus(&original) {
+            return Ok(false);
+        }
+
+        let mut staged = self.stage(&blob.content_hash, &original).await?;
+        if kept {
+            if let Some(original) = staged.original.take() {
+                let _ = fs::remove_file(original).await;
+            }
+        }
+        let _lock = self.lock.lock().await;
+        if !fs::try_exists(&path).await? {
+            drop(_lock);
+            self.discard(staged).await;
+            return Ok(false);
+        }
+        let size = fs::metadata(&staged.stored).await?.len() as i64;
+        self.commit(&blob.content_hash, staged).await?;
+        self.database
+            .set_blob_encoding(&blob.content_hash, size, self.opus_bitrate)
+            .await?;
+        Ok(true)
+    }
+
+    /// Renames the files stored before blobs existed to their hash, merging the duplicates,
+    /// then recounts every reference. Runs at startup, before anything else touches the files.
+    pub async fn migrate(&self) -> Result<()> {
+        let legacy_files = self.database.get_legacy_files().await?;
+        for file_name in &legacy_files {
+            let path = self.songs_dir.join(file_name);
+            let content = match fs::read(&path).await {
+                Ok(content) => content,
+                Err(err) => {
+                    tracing::warn!("Could not move {} to the blobs: {:?}", file_name, err);
+                    continue;
+                }
+            };
+            let content_hash = content_hash(&content);
+            let blob_path = self.songs_dir.join(&content_hash);
+            if fs::try_exists(&blob_path).await? {
+                fs::remove_file(&path).await?;
+            } else {
+                fs::rename(&path, &blob_path).await?;
+            }
+            self.database
+                .move_to_blob(file_name, &content_hash, content.len() as i64)
+                .await?;
+        }
+        if !legacy_files.is_empty() {
+            tracing::info!("Moved {} files to the blobs", legacy_files.len());
+        }
+
+        for content_hash in self.database.recount_blob_refs().await? {
+            tracing::info!("Removing unused blob {}", content_hash);
+            if let Err(err) = fs::remove_file(self.songs_dir.join(&content_hash)).await {
+                tracing::warn!("Could not remove {}: {:?}", content_hash, err);
+            }
+            if let Err(err) = remove_original(&self.originals_dir.join(&content_hash)).await {
+                tracing::warn!(
+                    "Could not remove the original of {}: {:?}",
+                    content_hash,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn remove_original(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
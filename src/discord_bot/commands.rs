@@ -1,24 +1,36 @@
-use std::sync::Arc;
-
+use super::archive;
+use super::archive::GuildSettings;
+use super::archive::Manifest;
+use super::archive::ManifestSound;
 use super::check_msg;
+use super::initiate_handler;
+use super::quota::format_bytes;
+use super::quota::Quota;
+use super::quota::Usage;
+use super::sounds;
 use super::Context;
-use super::RecognitionType;
-use super::SoundBoard;
+use super::ModelEntry;
 
+use crate::database::Published;
+use crate::metrics;
 use crate::speech_to_text::ModelLanguage;
 
+use std::collections::HashMap;
+use std::collections::HashSet;
+
 use anyhow::Result;
+use poise::ChoiceParameter;
+use poise::CreateReply;
 use serenity::all::Attachment;
 use serenity::all::ChannelId;
+use serenity::all::ChannelType;
+use serenity::all::CreateAttachment;
+use serenity::all::GuildChannel;
+use serenity::all::GuildId;
 use serenity::all::Mentionable;
-use songbird::Call;
-use songbird::CoreEvent;
-use songbird::Songbird;
+use serenity::all::Role;
+use serenity::all::User;
 use tokio::fs;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
-use uuid::Uuid;
 
 #[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn ping(ctx: Context<'_>) -> Result<()> {
@@ -46,16 +58,22 @@ pub async fn help(
 
 /// Information about the bot
 ///
-/// Shows the Github link and the owner's Discord tag.
+/// Shows the Github link and the owners' Discord tags.
 #[poise::command(prefix_command, slash_command)]
 pub async fn info(ctx: Context<'_>) -> Result<()> {
-    let owner = ctx.framework().options().owners.iter().next().unwrap();
-    let github_link = "https://github.com/112batuhan/verstappenbot";
+    let owners = ctx
+        .framework()
+        .options()
+        .owners
+        .iter()
+        .map(|owner| owner.mention().to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let github_link = &ctx.data().config.discord.github_url;
     check_msg(
         ctx.reply(format!(
             "Check out the Github for more information:\n{}\nYou can contact me on Discord:{}",
-            github_link,
-            owner.mention(),
+            github_link, owners,
         ))
         .await,
     );
@@ -65,7 +83,7 @@ pub async fn info(ctx: Context<'_>) -> Result<()> {
 /// invite link for the bot
 #[poise::command(prefix_command, slash_command)]
 pub async fn invite(ctx: Context<'_>) -> Result<()> {
-    let invite_link = "https://discord.com/oauth2/authorize?client_id=1213040318195437598&permissions=274914675712&scope=bot%20applications.commands";
+    let invite_link = &ctx.data().config.discord.invite_url;
     check_msg(
         ctx.reply(format!("Invite me to your server:\n{}", invite_link))
             .await,
@@ -108,7 +126,13 @@ pub async fn join(ctx: Context<'_>) -> Result<()> {
 
     match songbird_client.join(guild_id, connect_to).await {
         Ok(call_handler_lock) => {
-            initiate_handler(&ctx, songbird_client.clone(), call_handler_lock).await?;
+            initiate_handler(
+                ctx.data(),
+                ctx.serenity_context().cache.clone(),
+                guild_id,
+                call_handler_lock,
+            )
+            .await?;
             check_msg(ctx.reply(format!("Joined {}", connect_to.mention())).await);
         }
         Err(e) => {
@@ -142,18 +166,35 @@ pub async fn leave(ctx: Context<'_>) -> Result<()> {
     Ok(())
 }
 
+/// Suggests the prompts of the server's sounds while the user is typing.
+async fn autocomplete_prompt(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+    ctx.data()
+        .database
+        .search_prompts(&guild_id.to_string(), partial.trim())
+        .await
+        .unwrap_or_else(|why| {
+            tracing::error!("Error searching prompts: {:?}", why);
+            Vec::new()
+        })
+}
+
 /// Add a sound for the server.
 ///
-/// The sound can be any audio file. Maximum file size is 2mb.
+/// The sound can be any audio file. Maximum file size is 2mb by default.
+/// Every server has a limit on the number of sounds and their total size, see `/quota`.
 /// Supported languages: "english", "dutch", "turkish"
 #[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn add_sound(
     ctx: Context<'_>,
     #[description = "Prompt for the sound. Add phrases instead of single words to reduce false positives."]
     prompt: String,
-    #[description = "Language of the prompt: \"english\", \"dutch\", \"turkish\""] language: String,
+    #[description = "Language of the prompt"] language: ModelLanguage,
     #[description = "Sound you want to add"] attachment: Attachment,
 ) -> Result<()> {
+    let _task = ctx.data().tasks.token();
     let content = match attachment.download().await {
         Ok(content) => content,
         Err(why) => {
@@ -163,74 +204,64 @@ pub async fn add_sound(
         }
     };
 
-    if attachment.size > 2 * 1024 * 1024 {
-        let _ = ctx.reply("File size too large. Max 2mb.").await;
-        return Ok(());
-    }
-
-    if !attachment
-        .content_type
-        .is_some_and(|x| x.starts_with("audio"))
-    {
-        let _ = ctx.reply("Only audio files are supported").await;
-        return Ok(());
-    }
-
-    let id = Uuid::new_v4();
-    let mut file = match File::create(format!("./songs/{}", id)).await {
-        Ok(file) => file,
-        Err(why) => {
-            tracing::error!("Error creating file: {:?}", why);
-            let _ = ctx.reply("Error creating attachment").await;
+    let prompt = match sounds::validate(&ctx.data().config.storage, &prompt, &content) {
+        Ok(prompt) => prompt,
+        Err(reason) => {
+            let _ = ctx.reply(reason).await;
             return Ok(());
         }
     };
 
-    if let Err(why) = file.write_all(&content).await {
-        println!("Error writing to file: {:?}", why);
+    let server_id = ctx.guild_id().unwrap().to_string();
+    let quota_lock = ctx.data().quota_locks.lock(&server_id).await;
+    let quota =
+        Quota::of_guild(&ctx.data().database, &ctx.data().config.storage, &server_id).await?;
+    let usage = Usage::of_guild(&ctx.data().database, &server_id).await?;
+    if let Some(reason) = quota.rejects(&usage, content.len() as u64) {
+        let _ = ctx.reply(reason).await;
         return Ok(());
     }
 
-    let trimmed_prompt = prompt.trim();
-    if trimmed_prompt.is_empty() {
-        let _ = ctx.reply("Prompt cannot be empty").await;
-        return Ok(());
+    match sounds::store(ctx.data(), &server_id, prompt, language, &content).await {
+        Ok(true) => {}
+        Ok(false) => {
+            check_msg(
+                ctx.reply(format!(
+                    "This server already has a {} sound for {}",
+                    language.to_str(),
+                    prompt
+                ))
+                .await,
+            );
+            return Ok(());
+        }
+        Err(why) => {
+            tracing::error!("Error storing sound: {:?}", why);
+            let _ = ctx.reply("Error saving the sound").await;
+            return Ok(());
+        }
     }
+    drop(quota_lock);
 
-    if ModelLanguage::from_str(&language).is_none() {
-        let _ = ctx.reply("Invalid language").await;
-        return Ok(());
-    }
-
-    ctx.data()
-        .database
-        .add_sound(
-            &ctx.guild_id().unwrap().to_string(),
-            trimmed_prompt,
-            &language,
-            &id.to_string(),
-        )
-        .await?;
-
-    let _ = ctx
-        .reply(&format!(
-            "Saved {}, use join command to invite the bot.",
-            attachment.filename
-        ))
-        .await;
-    ctx.data().songbird.leave(ctx.guild_id().unwrap()).await?;
+    restart_call(ctx, ctx.guild_id().unwrap()).await;
+    check_msg(ctx.reply(format!("Saved {}", prompt)).await);
 
     Ok(())
 }
 
 /// Remove a sound from the server.
 ///
-/// You need to write the exact prompt.
+/// You need to write the exact prompt, slash command suggests the existing ones.
+/// Without a language the prompt is removed in every language.
 #[poise::command(prefix_command, slash_command, guild_only)]
 pub async fn remove_sound(
     ctx: Context<'_>,
-    #[description = "Prompt of the sound you want to delete"] prompt: String,
+    #[description = "Prompt of the sound you want to delete"]
+    #[autocomplete = "autocomplete_prompt"]
+    prompt: String,
+    #[description = "Language of the prompt, all if not set"] language: Option<ModelLanguage>,
 ) -> Result<()> {
+    let _task = ctx.data().tasks.token();
     let trimmed_prompt = prompt.trim();
     if trimmed_prompt.is_empty() {
         let _ = ctx.reply("Prompt cannot be empty").await;
@@ -240,22 +271,39 @@ pub async fn remove_sound(
     let deleted = ctx
         .data()
         .database
-        .remove_sound(&ctx.guild_id().unwrap().to_string(), trimmed_prompt)
+        .remove_sound(
+            &ctx.guild_id().unwrap().to_string(),
+            trimmed_prompt,
+            language.map(|language| language.to_str()),
+        )
         .await?;
+    if deleted.is_empty() {
+        check_msg(ctx.reply("No sound with that prompt").await);
+        return Ok(());
+    }
 
-    if let Err(err) = fs::remove_file(format!("songs/{}", deleted.file_name)).await {
-        tracing::error!("Error removing sound: {:?}", err);
+    let mut failed = false;
+    for sound in &deleted {
+        if let Err(err) = ctx.data().blobs.release(&sound.file_name).await {
+            tracing::error!("Error removing sound: {:?}", err);
+            failed = true;
+        }
+    }
+
+    restart_call(ctx, ctx.guild_id().unwrap()).await;
+    if failed {
         let _ = ctx.reply("Error removing sound").await;
         return Ok(());
     }
-
-    let _ = ctx
-        .reply(&format!(
-            "Removed {}, use join command to invite the bot.",
-            deleted.file_name
-        ))
-        .await;
-    ctx.data().songbird.leave(ctx.guild_id().unwrap()).await?;
+    let languages = deleted
+        .iter()
+        .map(|sound| sound.language.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    check_msg(
+        ctx.reply(format!("Removed {} ({})", trimmed_prompt, languages))
+            .await,
+    );
 
     Ok(())
 }
@@ -271,7 +319,13 @@ pub async fn list_sounds(ctx: Context<'_>) -> Result<()> {
 
     let sounds = sounds
         .into_iter()
-        .map(|sound| format!("{} - {}", sound.prompt, sound.language))
+        .map(|sound| match sound.catalog_id {
+            Some(catalog_id) => format!(
+                "{} - {} (from the catalog, #{})",
+                sound.prompt, sound.language, catalog_id
+            ),
+            None => format!("{} - {}", sound.prompt, sound.language),
+        })
         .collect::<Vec<String>>()
         .join("\n");
 
@@ -286,49 +340,1252 @@ pub async fn list_sounds(ctx: Context<'_>) -> Result<()> {
     Ok(())
 }
 
-async fn initiate_handler(
-    ctx: &Context<'_>,
-    songbird: Arc<Songbird>,
-    call_handler_lock: Arc<Mutex<Call>>,
+/// Download the server's sounds as a zip archive.
+///
+/// The archive can be added to any server with `/import`.
+#[poise::command(prefix_command, slash_command, guild_only)]
+pub async fn export(ctx: Context<'_>) -> Result<()> {
+    ctx.defer().await?;
+    let server_id = ctx.guild_id().unwrap().to_string();
+    let data = ctx.data();
+    let db_sounds = data.database.get_sounds(&server_id).await?;
+    if db_sounds.is_empty() {
+        check_msg(ctx.reply("No sounds found").await);
+        return Ok(());
+    }
+
+    let mut sounds = Vec::new();
+    let mut files = Vec::new();
+    let mut missing = 0;
+    for sound in db_sounds {
+        match data.blobs.read_original(&sound.file_name).await {
+            Ok(content) => {
+                let path = format!("sounds/{}", sound.file_name);
+                sounds.push(ManifestSound {
+                    prompt: sound.prompt,
+                    language: sound.language,
+                    file: path.clone(),
+                });
+                files.push((path, content));
+            }
+            Err(err) => {
+                tracing::warn!("Could not read {}: {:?}", sound.file_name, err);
+                missing += 1;
+            }
+        }
+    }
+    let settings = GuildSettings {
+        listen_roles: data.database.get_listen_roles(&server_id).await?,
+        auto_join_channels: data.database.get_auto_join_channels(&server_id).await?,
+    };
+
+    let mut content = format!("Exported {} sounds", sounds.len());
+    if missing > 0 {
+        content.push_str(&format!(
+            ", {} were left out because their files are missing",
+            missing
+        ));
+    }
+    let manifest = Manifest::new(server_id, sounds, settings);
+    let archive = tokio::task::spawn_blocking(move || archive::write(&manifest, &files)).await??;
+
+    let reply = CreateReply::default()
+        .content(content)
+        .attachment(CreateAttachment::bytes(archive, "sounds.zip"));
+    if let Err(why) = ctx.send(reply).await {
+        tracing::error!("Error sending the archive: {:?}", why);
+        check_msg(
+            ctx.reply("Could not upload the archive, it's probably too large")
+                .await,
+        );
+    }
+    Ok(())
+}
+
+/// Add the sounds of an archive made with `/export`.
+///
+/// Every sound is checked the same way as in `add_sound`, sounds with a prompt that already exists are skipped.
+/// Listen roles and auto join channels are only restored into the server the archive came from.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "Archive made with /export"] archive: Attachment,
 ) -> Result<()> {
+    let _task = ctx.data().tasks.token();
+    ctx.defer().await?;
     let guild_id = ctx.guild_id().unwrap();
-    let sounds = ctx
+    let server_id = guild_id.to_string();
+    let data = ctx.data();
+
+    let quota = Quota::of_guild(&data.database, &data.config.storage, &server_id).await?;
+    if archive.size as u64 > quota.max_bytes {
+        check_msg(
+            ctx.reply("The archive is larger than this server's storage quota")
+                .await,
+        );
+        return Ok(());
+    }
+    let content = match archive.download().await {
+        Ok(content) => content,
+        Err(why) => {
+            tracing::error!("Error downloading attachment: {:?}", why);
+            check_msg(ctx.reply("Error downloading attachment").await);
+            return Ok(());
+        }
+    };
+
+    let max_file_bytes = data.config.storage.max_upload_bytes;
+    let max_sounds = quota.max_sounds as usize;
+    let read =
+        tokio::task::spawn_blocking(move || archive::read(content, max_file_bytes, max_sounds))
+            .await?;
+    let (manifest, archived) = match read {
+        Ok(read) => read,
+        Err(err) => {
+            check_msg(ctx.reply(format!("Invalid archive: {:#}", err)).await);
+            return Ok(());
+        }
+    };
+
+    let quota_lock = data.quota_locks.lock(&server_id).await;
+    let mut usage = Usage::of_guild(&data.database, &server_id).await?;
+    let mut existing: HashSet<(String, String)> = data
+        .database
+        .get_sounds(&server_id)
+        .await?
+        .into_iter()
+        .map(|sound| (sound.prompt, sound.language))
+        .collect();
+    let mut imported = 0;
+    let mut skipped = Vec::new();
+    for sound in archived {
+        let label = format!("{} ({})", sound.prompt.trim(), sound.language);
+        let Some(language) = ModelLanguage::from_name(&sound.language) else {
+            skipped.push(format!("{}: unsupported language", label));
+            continue;
+        };
+        let content = match sound.content {
+            Ok(content) => content,
+            Err(reason) => {
+                skipped.push(format!("{}: {}", label, reason));
+                continue;
+            }
+        };
+        let prompt = match sounds::validate(&data.config.storage, &sound.prompt, &content) {
+            Ok(prompt) => prompt,
+            Err(reason) => {
+                skipped.push(format!("{}: {}", label, reason));
+                continue;
+            }
+        };
+        if !existing.insert((prompt.to_string(), language.to_str().to_string())) {
+            skipped.push(format!("{}: already exists", label));
+            continue;
+        }
+        if let Some(reason) = quota.rejects(&usage, content.len() as u64) {
+            skipped.push(format!("{}: {}", label, reason));
+            continue;
+        }
+        match sounds::store(data, &server_id, prompt, language, &content).await {
+            Ok(true) => {}
+            Ok(false) => {
+                skipped.push(format!("{}: already exists", label));
+                continue;
+            }
+            Err(why) => {
+                tracing::error!("Error storing sound: {:?}", why);
+                skipped.push(format!("{}: could not be saved", label));
+                continue;
+            }
+        }
+        usage.sounds += 1;
+        usage.bytes += content.len() as u64;
+        imported += 1;
+    }
+    drop(quota_lock);
+
+    let mut lines = vec![format!("Imported {} sounds", imported)];
+    if manifest.guild_id == server_id {
+        let (roles, channels) = restore_settings(ctx, guild_id, &manifest.settings).await?;
+        lines.push(format!(
+            "Restored {} listen roles and {} auto join channels",
+            roles, channels
+        ));
+    }
+    if !skipped.is_empty() {
+        lines.push(format!("Skipped {}:", skipped.len()));
+        lines.extend(skipped.into_iter().map(|line| format!("- {}", line)));
+    }
+    if imported > 0 || manifest.guild_id == server_id {
+        restart_call(ctx, guild_id).await;
+    }
+    check_msg(ctx.reply(join_lines_capped(&lines)).await);
+
+    Ok(())
+}
+
+/// Adds the archived listen roles and auto join channels that still exist in the guild.
+async fn restore_settings(
+    ctx: Context<'_>,
+    guild_id: GuildId,
+    settings: &GuildSettings,
+) -> Result<(usize, usize)> {
+    let (roles, channels): (Vec<String>, Vec<String>) = {
+        let Some(guild) = ctx.guild() else {
+            return Ok((0, 0));
+        };
+        (
+            settings
+                .listen_roles
+                .iter()
+                .filter(|role_id| {
+                    role_id
+                        .parse()
+                        .is_ok_and(|role_id| guild.roles.contains_key(&role_id))
+                })
+                .cloned()
+                .collect(),
+            settings
+                .auto_join_channels
+                .iter()
+                .filter(|channel_id| {
+                    channel_id
+                        .parse()
+                        .is_ok_and(|channel_id| guild.channels.contains_key(&channel_id))
+                })
+                .cloned()
+                .collect(),
+        )
+    };
+
+    let server_id = guild_id.to_string();
+    let database = &ctx.data().database;
+    let mut restored_roles = 0;
+    for role_id in &roles {
+        if database.add_listen_role(&server_id, role_id).await? {
+            restored_roles += 1;
+        }
+    }
+    let mut restored_channels = 0;
+    for channel_id in &channels {
+        if database
+            .add_auto_join_channel(&server_id, channel_id)
+            .await?
+        {
+            restored_channels += 1;
+        }
+    }
+    Ok((restored_roles, restored_channels))
+}
+
+/// Joins the lines, leaving out the ones that don't fit in a Discord message.
+fn join_lines_capped(lines: &[String]) -> String {
+    const MAX_LENGTH: usize = 1900;
+    let mut message = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if message.len() + line.len() + 1 > MAX_LENGTH {
+            message.push_str(&format!("... and {} more", lines.len() - index));
+            break;
+        }
+        message.push_str(line);
+        message.push('\n');
+    }
+    message
+}
+
+/// Shows how many sounds the server has and how much storage they use.
+#[poise::command(prefix_command, slash_command, guild_only)]
+pub async fn quota(ctx: Context<'_>) -> Result<()> {
+    let server_id = ctx.guild_id().unwrap().to_string();
+    let quota =
+        Quota::of_guild(&ctx.data().database, &ctx.data().config.storage, &server_id).await?;
+    let usage = Usage::of_guild(&ctx.data().database, &server_id).await?;
+
+    check_msg(
+        ctx.reply(format!(
+            "Sounds: {} / {}\nStorage: {} / {}",
+            usage.sounds,
+            quota.max_sounds,
+            format_bytes(usage.bytes),
+            format_bytes(quota.max_bytes)
+        ))
+        .await,
+    );
+    Ok(())
+}
+
+/// Share sounds between servers.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("publish_sound", "search_catalog", "install_sound", "unpublish_sound"),
+    subcommand_required
+)]
+pub async fn catalog(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Add one of the server's sounds to the public catalog, so every server can install it.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    rename = "publish",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn publish_sound(
+    ctx: Context<'_>,
+    #[description = "Prompt of the sound you want to publish"]
+    #[autocomplete = "autocomplete_prompt"]
+    prompt: String,
+    #[description = "Language of the prompt"] language: ModelLanguage,
+) -> Result<()> {
+    let server_id = ctx.guild_id().unwrap().to_string();
+    let data = ctx.data();
+    let prompt = prompt.trim();
+    let Some(sound) = data
+        .database
+        .get_sound_file_info(&server_id, prompt, language.to_str())
+        .await?
+    else {
+        check_msg(ctx.reply("No sound with that prompt and language").await);
+        return Ok(());
+    };
+
+    // Only sounds whose file was missing when the files were moved to the blobs have no hash.
+    let Some(content_hash) = &sound.content_hash else {
+        check_msg(ctx.reply("The file of this sound is missing").await);
+        return Ok(());
+    };
+
+    let published = data
+        .blobs
+        .publish(
+            &sound,
+            content_hash,
+            prompt,
+            language.to_str(),
+            &ctx.author().id.to_string(),
+            &server_id,
+        )
+        .await?;
+    match published {
+        Published::Added(id) => check_msg(
+            ctx.reply(format!(
+                "Published {} as #{}, install it with `/catalog install {}`",
+                prompt, id, id
+            ))
+            .await,
+        ),
+        Published::AlreadyPublished => {
+            check_msg(ctx.reply("This sound is already in the catalog").await)
+        }
+        Published::MissingBlob => check_msg(ctx.reply("The file of this sound is missing").await),
+    }
+    Ok(())
+}
+
+/// Search the public catalog.
+#[poise::command(prefix_command, slash_command, rename = "search")]
+pub async fn search_catalog(
+    ctx: Context<'_>,
+    #[description = "Part of the prompt, leave it out to see the most installed sounds"]
+    query: Option<String>,
+    #[description = "Language of the prompt"] language: Option<ModelLanguage>,
+) -> Result<()> {
+    let found = ctx
+        .data()
+        .database
+        .search_catalog(
+            query.as_deref().unwrap_or("").trim(),
+            language.map(|language| language.to_str()),
+        )
+        .await?
+        .into_iter()
+        .map(|sound| {
+            format!(
+                "#{} {} - {} ({} installs)",
+                sound.id, sound.prompt, sound.language, sound.installs
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let found = if found.is_empty() {
+        "No sounds found".to_string()
+    } else {
+        format!("```{}```", found)
+    };
+    check_msg(ctx.reply(found).await);
+    Ok(())
+}
+
+/// Add a sound from the public catalog to the server.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "install")]
+pub async fn install_sound(
+    ctx: Context<'_>,
+    #[description = "Number of the sound in the catalog"] id: u32,
+) -> Result<()> {
+    let _task = ctx.data().tasks.token();
+    let guild_id = ctx.guild_id().unwrap();
+    let server_id = guild_id.to_string();
+    let data = ctx.data();
+    let Some(sound) = data.database.get_catalog_sound(id as i32).await? else {
+        check_msg(ctx.reply("No sound with that number in the catalog").await);
+        return Ok(());
+    };
+
+    let quota_lock = data.quota_locks.lock(&server_id).await;
+    let exists = data
+        .database
+        .get_sound_file_info(&server_id, &sound.prompt, &sound.language)
+        .await?
+        .is_some();
+    if exists {
+        check_msg(
+            ctx.reply(format!(
+                "This server already has a {} sound for {}",
+                sound.language, sound.prompt
+            ))
+            .await,
+        );
+        return Ok(());
+    }
+
+    let quota = Quota::of_guild(&data.database, &data.config.storage, &server_id).await?;
+    let usage = Usage::of_guild(&data.database, &server_id).await?;
+    if let Some(reason) = quota.rejects(&usage, sound.size as u64) {
+        check_msg(ctx.reply(reason).await);
+        return Ok(());
+    }
+    if !data.blobs.install(&server_id, &sound).await? {
+        tracing::error!("File of catalog sound #{} is missing", sound.id);
+        check_msg(ctx.reply("The file of this sound is missing").await);
+        return Ok(());
+    }
+    drop(quota_lock);
+    restart_call(ctx, guild_id).await;
+    check_msg(ctx.reply(format!("Installed {}", sound.prompt)).await);
+
+    Ok(())
+}
+
+/// Remove a sound you published from the catalog. Servers that installed it keep it.
+#[poise::command(prefix_command, slash_command, rename = "unpublish")]
+pub async fn unpublish_sound(
+    ctx: Context<'_>,
+    #[description = "Number of the sound in the catalog"] id: u32,
+) -> Result<()> {
+    let data = ctx.data();
+    let Some(sound) = data.database.get_catalog_sound(id as i32).await? else {
+        check_msg(ctx.reply("No sound with that number in the catalog").await);
+        return Ok(());
+    };
+    let is_owner = ctx.framework().options().owners.contains(&ctx.author().id);
+    if sound.publisher_id != ctx.author().id.to_string() && !is_owner {
+        check_msg(
+            ctx.reply("Only the user who published it can remove it")
+                .await,
+        );
+        return Ok(());
+    }
+
+    data.database.unpublish_sound(sound.id).await?;
+    if let Err(err) = data.blobs.release(&sound.file_name).await {
+        tracing::error!("Error removing sound: {:?}", err);
+    }
+    check_msg(
+        ctx.reply(format!("Removed #{} from the catalog", sound.id))
+            .await,
+    );
+    Ok(())
+}
+
+/// Control whether the bot listens to you.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("optout", "optin"),
+    subcommand_required
+)]
+pub async fn privacy(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Stop the bot from listening to you in every server.
+///
+/// Your audio won't be transcribed anymore, prompts you say won't play sounds.
+#[poise::command(prefix_command, slash_command)]
+pub async fn optout(ctx: Context<'_>) -> Result<()> {
+    let user_id = ctx.author().id;
+    ctx.data().database.opt_out(&user_id.to_string()).await?;
+    ctx.data().opted_out_users.insert(user_id.get());
+
+    check_msg(ctx.reply("The bot won't listen to you anymore.").await);
+    Ok(())
+}
+
+/// Let the bot listen to you again.
+#[poise::command(prefix_command, slash_command)]
+pub async fn optin(ctx: Context<'_>) -> Result<()> {
+    let user_id = ctx.author().id;
+    ctx.data().database.opt_in(&user_id.to_string()).await?;
+    ctx.data().opted_out_users.remove(&user_id.get());
+
+    check_msg(
+        ctx.reply("The bot will listen to you the next time you speak.")
+            .await,
+    );
+    Ok(())
+}
+
+/// Choose the language you speak in this server.
+///
+/// The bot only listens to you in that language, which makes it faster and more accurate.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    subcommands("set_language", "clear_language", "show_language"),
+    subcommand_required
+)]
+pub async fn language(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Only listen to you in this language.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "set")]
+pub async fn set_language(
+    ctx: Context<'_>,
+    #[description = "Language that you speak"] language: ModelLanguage,
+) -> Result<()> {
+    let guild_id = ctx.guild_id().unwrap();
+    let user_id = ctx.author().id;
+    ctx.data()
+        .database
+        .set_user_language(
+            &guild_id.to_string(),
+            &user_id.to_string(),
+            language.to_str(),
+        )
+        .await?;
+    ctx.data()
+        .user_languages
+        .insert((guild_id.get(), user_id.get()), language);
+
+    check_msg(
+        ctx.reply(format!(
+            "The bot will only listen to you in {} the next time you join a voice channel.",
+            language.to_str()
+        ))
+        .await,
+    );
+    Ok(())
+}
+
+/// Listen to you in every language again.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "clear")]
+pub async fn clear_language(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx.guild_id().unwrap();
+    let user_id = ctx.author().id;
+    let cleared = ctx
+        .data()
+        .database
+        .clear_user_language(&guild_id.to_string(), &user_id.to_string())
+        .await?;
+    ctx.data()
+        .user_languages
+        .remove(&(guild_id.get(), user_id.get()));
+
+    if !cleared {
+        check_msg(ctx.reply("You didn't set a language").await);
+        return Ok(());
+    }
+
+    check_msg(
+        ctx.reply(
+            "The bot will listen to you in every language the next time you join a voice channel.",
+        )
+        .await,
+    );
+    Ok(())
+}
+
+/// Show the language the bot listens to you in.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "show")]
+pub async fn show_language(ctx: Context<'_>) -> Result<()> {
+    let language = ctx
+        .data()
+        .user_languages
+        .get(&(ctx.guild_id().unwrap().get(), ctx.author().id.get()))
+        .map(|language| *language);
+
+    let reply = match language {
+        Some(language) => format!("The bot listens to you in {}", language.to_str()),
+        None => "No language set, the bot listens to you in every language".to_string(),
+    };
+    check_msg(ctx.reply(reply).await);
+    Ok(())
+}
+
+/// Only listen to the members with certain roles.
+///
+/// If no roles are set, the bot listens to everyone.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("add_listen_role", "remove_listen_role", "list_listen_roles"),
+    subcommand_required
+)]
+pub async fn listen_roles(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Add a role to the list of roles the bot listens to.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    rename = "add",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn add_listen_role(
+    ctx: Context<'_>,
+    #[description = "Role that the bot should listen to"] role: Role,
+) -> Result<()> {
+    let added = ctx
+        .data()
+        .database
+        .add_listen_role(&ctx.guild_id().unwrap().to_string(), &role.id.to_string())
+        .await?;
+
+    if !added {
+        check_msg(ctx.reply("Role is already in the list").await);
+        return Ok(());
+    }
+
+    restart_call(ctx, ctx.guild_id().unwrap()).await;
+    check_msg(ctx.reply(format!("Added {}", role.mention())).await);
+
+    Ok(())
+}
+
+/// Remove a role from the list of roles the bot listens to.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    rename = "remove",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn remove_listen_role(
+    ctx: Context<'_>,
+    #[description = "Role that the bot shouldn't listen to anymore"] role: Role,
+) -> Result<()> {
+    let removed = ctx
         .data()
         .database
-        .get_sounds(guild_id.to_string().as_str())
+        .remove_listen_role(&ctx.guild_id().unwrap().to_string(), &role.id.to_string())
         .await?;
 
-    let sound_board = sounds
+    if !removed {
+        check_msg(ctx.reply("Role is not in the list").await);
+        return Ok(());
+    }
+
+    restart_call(ctx, ctx.guild_id().unwrap()).await;
+    check_msg(ctx.reply(format!("Removed {}", role.mention())).await);
+
+    Ok(())
+}
+
+/// Rebuilds the handler of the guild's call if the bot is in one, so changed settings apply right away.
+async fn restart_call(ctx: Context<'_>, guild_id: GuildId) {
+    let Some(call_handler_lock) = ctx.data().songbird.get(guild_id) else {
+        return;
+    };
+    if let Err(err) = initiate_handler(
+        ctx.data(),
+        ctx.serenity_context().cache.clone(),
+        guild_id,
+        call_handler_lock,
+    )
+    .await
+    {
+        tracing::error!("Error restarting the call in {}: {:?}", guild_id, err);
+    }
+}
+
+/// List the roles the bot listens to.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "list")]
+pub async fn list_listen_roles(ctx: Context<'_>) -> Result<()> {
+    let roles = ctx
+        .data()
+        .database
+        .get_listen_roles(&ctx.guild_id().unwrap().to_string())
+        .await?
         .into_iter()
-        .fold(SoundBoard::new(), |sound_board, sound| {
-            let recognition_type = if sound.prompt.split_whitespace().count() > 1 {
-                RecognitionType::PHRASE
+        .map(|role_id| format!("<@&{}>", role_id))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let roles = if roles.is_empty() {
+        "No roles set, the bot listens to everyone".to_string()
+    } else {
+        roles
+    };
+
+    check_msg(ctx.reply(roles).await);
+
+    Ok(())
+}
+
+/// Join voice channels automatically when someone enters them.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("add_auto_join", "remove_auto_join", "list_auto_join"),
+    subcommand_required
+)]
+pub async fn auto_join(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Join this voice channel when the first member enters it.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    rename = "add",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn add_auto_join(
+    ctx: Context<'_>,
+    #[description = "Voice channel to join automatically"]
+    #[channel_types("Voice", "Stage")]
+    channel: GuildChannel,
+) -> Result<()> {
+    if !matches!(channel.kind, ChannelType::Voice | ChannelType::Stage) {
+        check_msg(ctx.reply("Only voice channels can be joined").await);
+        return Ok(());
+    }
+
+    let added = ctx
+        .data()
+        .database
+        .add_auto_join_channel(
+            &ctx.guild_id().unwrap().to_string(),
+            &channel.id.to_string(),
+        )
+        .await?;
+
+    if added {
+        check_msg(
+            ctx.reply(format!("Will join {} automatically", channel.mention()))
+                .await,
+        );
+    } else {
+        check_msg(ctx.reply("Channel is already in the list").await);
+    }
+
+    Ok(())
+}
+
+/// Stop joining this voice channel automatically.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    rename = "remove",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn remove_auto_join(
+    ctx: Context<'_>,
+    #[description = "Voice channel to stop joining automatically"]
+    #[channel_types("Voice", "Stage")]
+    channel: GuildChannel,
+) -> Result<()> {
+    let removed = ctx
+        .data()
+        .database
+        .remove_auto_join_channel(
+            &ctx.guild_id().unwrap().to_string(),
+            &channel.id.to_string(),
+        )
+        .await?;
+
+    if removed {
+        check_msg(
+            ctx.reply(format!("Won't join {} automatically", channel.mention()))
+                .await,
+        );
+    } else {
+        check_msg(ctx.reply("Channel is not in the list").await);
+    }
+
+    Ok(())
+}
+
+/// List the voice channels that the bot joins automatically.
+#[poise::command(prefix_command, slash_command, guild_only, rename = "list")]
+pub async fn list_auto_join(ctx: Context<'_>) -> Result<()> {
+    let channels = ctx
+        .data()
+        .database
+        .get_auto_join_channels(&ctx.guild_id().unwrap().to_string())
+        .await?
+        .into_iter()
+        .map(|channel_id| format!("<#{}>", channel_id))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let channels = if channels.is_empty() {
+        "No channels set".to_string()
+    } else {
+        channels
+    };
+
+    check_msg(ctx.reply(channels).await);
+
+    Ok(())
+}
+
+/// Commands for the owners of the bot.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    hide_in_help,
+    subcommands(
+        "stats",
+        "force_leave",
+        "reload_models",
+        "block_guild",
+        "unblock_guild",
+        "block_user",
+        "unblock_user",
+        "storage",
+        "reencode",
+        "set_quota",
+        "reset_quota"
+    ),
+    subcommand_required
+)]
+pub async fn admin(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Guild ids are taken as text, slash command integers are too small for them.
+fn parse_guild_id(guild_id: &str) -> Option<GuildId> {
+    guild_id.trim().parse().ok()
+}
+
+/// Usage of the bot, or of a single guild if its id is given.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: Option<String>,
+) -> Result<()> {
+    let data = ctx.data();
+    let Some(guild_id) = guild_id else {
+        let sound_files = data.database.get_sound_files().await?;
+        let sound_guilds = sound_files
+            .iter()
+            .map(|file| file.server_id.as_str())
+            .collect::<HashSet<&str>>()
+            .len();
+        let languages = data
+            .models
+            .current()
+            .iter()
+            .map(|model| model.language.to_str().to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let stats = [
+            format!("Guilds: {}", ctx.cache().guild_count()),
+            format!("Calls: {}", data.songbird.iter().count()),
+            format!("Sounds: {} in {} guilds", sound_files.len(), sound_guilds),
+            format!("Models: {}", languages),
+            format!("Opted out users: {}", data.opted_out_users.len()),
+            format!("Blocked guilds: {}", data.blocked_guilds.len()),
+            format!("Blocked users: {}", data.blocked_users.len()),
+            format!("Dropped frames: {}", metrics::FRAMES_DROPPED.get()),
+        ];
+        check_msg(ctx.reply(format!("```{}```", stats.join("\n"))).await);
+        return Ok(());
+    };
+
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    let guild = ctx
+        .cache()
+        .guild(guild_id)
+        .map(|guild| (guild.name.clone(), guild.member_count));
+    let sounds = data.database.get_sounds(&guild_id.to_string()).await?;
+    let call = match data.songbird.get(guild_id) {
+        Some(call_handler) => match call_handler.lock().await.current_channel() {
+            Some(channel_id) => format!("<#{}>", channel_id.0),
+            None => "Connecting".to_string(),
+        },
+        None => "None".to_string(),
+    };
+
+    let stats = [
+        match guild {
+            Some((name, member_count)) => format!("Guild: {} ({} members)", name, member_count),
+            None => "Guild: not in the cache".to_string(),
+        },
+        format!("Sounds: {}", sounds.len()),
+        format!("Call: {}", call),
+        format!(
+            "Blocked: {}",
+            if data.blocked_guilds.contains(&guild_id.get()) {
+                "yes"
             } else {
-                RecognitionType::WORD
-            };
-            sound_board.add_song(
-                &sound.prompt,
-                recognition_type,
-                ModelLanguage::from_str(&sound.language).unwrap(),
-                format!("songs/{}", sound.file_name).as_str(),
-            )
-        });
+                "no"
+            }
+        ),
+    ];
+    check_msg(ctx.reply(stats.join("\n")).await);
 
-    let player = sound_board.get_player(songbird.clone(), guild_id).await;
-    let models = ctx.data().models.clone();
+    Ok(())
+}
 
-    let voice_handler = sound_board.get_voice_handler(models, player);
+/// Leaves the call of a guild.
+#[poise::command(prefix_command, slash_command, owners_only, rename = "leave")]
+pub async fn force_leave(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: String,
+) -> Result<()> {
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    let songbird_client = &ctx.data().songbird;
+    if songbird_client.get(guild_id).is_none() {
+        check_msg(ctx.reply("Not in a call in that guild").await);
+        return Ok(());
+    }
+
+    songbird_client.remove(guild_id).await?;
+    check_msg(ctx.reply(format!("Left the call in {}", guild_id)).await);
+
+    Ok(())
+}
+
+/// Loads the models from disk again.
+///
+/// The calls are restarted with the new models. If a model fails to load, the old ones are kept.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn reload_models(ctx: Context<'_>) -> Result<()> {
+    ctx.defer().await?;
+    let config = ctx.data().config.clone();
+    let loaded = tokio::task::spawn_blocking(move || {
+        config
+            .models
+            .iter()
+            .map(ModelEntry::load)
+            .collect::<Result<Vec<ModelEntry>>>()
+    })
+    .await?;
+    let models = match loaded {
+        Ok(models) => models,
+        Err(err) => {
+            tracing::error!("Error reloading the models: {:?}", err);
+            check_msg(ctx.reply(format!("Failed: {:#}", err)).await);
+            return Ok(());
+        }
+    };
+    let count = models.len();
+    ctx.data().models.replace(models);
+    tracing::info!("Reloaded {} models", count);
+
+    let calls: Vec<_> = ctx.data().songbird.iter().collect();
+    for (guild_id, call_handler_lock) in &calls {
+        let guild_id = GuildId::new(guild_id.0.get());
+        if let Err(err) = initiate_handler(
+            ctx.data(),
+            ctx.serenity_context().cache.clone(),
+            guild_id,
+            call_handler_lock.clone(),
+        )
+        .await
+        {
+            tracing::error!("Error restarting the call in {}: {:?}", guild_id, err);
+        }
+    }
+
+    check_msg(
+        ctx.reply(format!(
+            "Reloaded {} models and restarted {} calls",
+            count,
+            calls.len()
+        ))
+        .await,
+    );
+    Ok(())
+}
+
+/// Stops a guild from using the bot and leaves its call.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn block_guild(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: String,
+) -> Result<()> {
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    let data = ctx.data();
+    if !data.database.block_guild(&guild_id.to_string()).await? {
+        check_msg(ctx.reply("Guild is already blocked").await);
+        return Ok(());
+    }
+    data.blocked_guilds.insert(guild_id.get());
+    if data.songbird.get(guild_id).is_some() {
+        data.songbird.remove(guild_id).await?;
+    }
+
+    check_msg(ctx.reply(format!("Blocked {}", guild_id)).await);
+    Ok(())
+}
+
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn unblock_guild(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: String,
+) -> Result<()> {
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    if !ctx
+        .data()
+        .database
+        .unblock_guild(&guild_id.to_string())
+        .await?
+    {
+        check_msg(ctx.reply("Guild is not blocked").await);
+        return Ok(());
+    }
+    ctx.data().blocked_guilds.remove(&guild_id.get());
+
+    check_msg(ctx.reply(format!("Unblocked {}", guild_id)).await);
+    Ok(())
+}
+
+/// Stops a user from using the bot's commands and from being listened to.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn block_user(
+    ctx: Context<'_>,
+    #[description = "User to block"] user: User,
+) -> Result<()> {
+    if !ctx.data().database.block_user(&user.id.to_string()).await? {
+        check_msg(ctx.reply("User is already blocked").await);
+        return Ok(());
+    }
+    ctx.data().blocked_users.insert(user.id.get());
+
+    check_msg(ctx.reply(format!("Blocked {}", user.mention())).await);
+    Ok(())
+}
 
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn unblock_user(
+    ctx: Context<'_>,
+    #[description = "User to unblock"] user: User,
+) -> Result<()> {
+    if !ctx
+        .data()
+        .database
+        .unblock_user(&user.id.to_string())
+        .await?
     {
-        let mut call_handler = call_handler_lock.lock().await;
+        check_msg(ctx.reply("User is not blocked").await);
+        return Ok(());
+    }
+    ctx.data().blocked_users.remove(&user.id.get());
+
+    check_msg(ctx.reply(format!("Unblocked {}", user.mention())).await);
+    Ok(())
+}
+
+/// Disk usage of the sounds, the guilds using the most and the files no sound refers to.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn storage(ctx: Context<'_>) -> Result<()> {
+    let sound_files = ctx.data().database.get_sound_files().await?;
+
+    let mut file_sizes: HashMap<String, u64> = HashMap::new();
+    let mut entries = fs::read_dir(&ctx.data().config.storage.songs_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            file_sizes.insert(
+                entry.file_name().to_string_lossy().into_owned(),
+                metadata.len(),
+            );
+        }
+    }
+
+    let mut guild_sizes: HashMap<&str, u64> = HashMap::new();
+    let mut missing = 0;
+    for file in &sound_files {
+        match file_sizes.get(&file.file_name) {
+            Some(size) => *guild_sizes.entry(&file.server_id).or_default() += size,
+            None => missing += 1,
+        }
+    }
+    let catalog_files = ctx.data().database.get_catalog_file_names().await?;
+    let referenced: HashSet<&str> = sound_files
+        .iter()
+        .map(|file| file.file_name.as_str())
+        .chain(catalog_files.iter().map(|file_name| file_name.as_str()))
+        .collect();
+    let (orphans, orphan_bytes) = file_sizes
+        .iter()
+        .filter(|(file_name, _)| !referenced.contains(file_name.as_str()))
+        .fold((0, 0), |(count, bytes), (_, size)| {
+            (count + 1, bytes + size)
+        });
+
+    let mut guild_sizes: Vec<(&str, u64)> = guild_sizes.into_iter().collect();
+    guild_sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+    let mut lines = vec![
+        format!(
+            "Total: {} in {} files",
+            format_bytes(file_sizes.values().sum()),
+            file_sizes.len()
+        ),
+        format!("Unused files: {} ({})", orphans, format_bytes(orphan_bytes)),
+        format!("Sounds with missing files: {}", missing),
+        "Largest guilds:".to_string(),
+    ];
+    lines.extend(
+        guild_sizes
+            .iter()
+            .take(5)
+            .map(|(guild_id, size)| format!("  {} - {}", guild_id, format_bytes(*size))),
+    );
+    check_msg(ctx.reply(format!("```{}```", lines.join("\n"))).await);
+
+    Ok(())
+}
+
+/// Encodes the stored sounds again at the configured bitrate.
+///
+/// Sounds are encoded from their originals if they were kept. Files stored before uploads were encoded are encoded as well.
+/// Calls pick the new files up the next time they are started.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn reencode(ctx: Context<'_>) -> Result<()> {
+    ctx.defer().await?;
+    match ctx.data().blobs.reencode().await {
+        Ok(count) => {
+            tracing::info!("Re-encoded {} sounds", count);
+            check_msg(ctx.reply(format!("Re-encoded {} sounds", count)).await);
+        }
+        Err(err) => {
+            tracing::error!("Error re-encoding the sounds: {:?}", err);
+            check_msg(ctx.reply(format!("Failed: {:#}", err)).await);
+        }
+    }
+    Ok(())
+}
 
-        call_handler.remove_all_global_events();
+/// Changes the quota of a guild. Limits that are left out use the defaults.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn set_quota(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: String,
+    #[description = "Maximum number of sounds"] max_sounds: Option<u32>,
+    #[description = "Maximum storage in megabytes"] max_megabytes: Option<u32>,
+) -> Result<()> {
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    ctx.data()
+        .database
+        .set_guild_quota(
+            &guild_id.to_string(),
+            max_sounds.map(|max| max.min(i32::MAX as u32) as i32),
+            max_megabytes.map(|max| max as i64 * 1024 * 1024),
+        )
+        .await?;
+
+    let quota = Quota::of_guild(
+        &ctx.data().database,
+        &ctx.data().config.storage,
+        &guild_id.to_string(),
+    )
+    .await?;
+    check_msg(
+        ctx.reply(format!(
+            "Quota of {} is {} sounds and {}",
+            guild_id,
+            quota.max_sounds,
+            format_bytes(quota.max_bytes)
+        ))
+        .await,
+    );
+    Ok(())
+}
 
-        call_handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), voice_handler.clone());
-        call_handler.add_global_event(CoreEvent::ClientDisconnect.into(), voice_handler.clone());
-        call_handler.add_global_event(CoreEvent::VoiceTick.into(), voice_handler.clone());
-        call_handler.add_global_event(CoreEvent::DriverDisconnect.into(), voice_handler.clone());
-        call_handler.add_global_event(CoreEvent::DriverReconnect.into(), voice_handler);
+/// Puts a guild back on the default quota.
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn reset_quota(
+    ctx: Context<'_>,
+    #[description = "Id of the guild"] guild_id: String,
+) -> Result<()> {
+    let Some(guild_id) = parse_guild_id(&guild_id) else {
+        check_msg(ctx.reply("Invalid guild id").await);
+        return Ok(());
+    };
+    if !ctx
+        .data()
+        .database
+        .remove_guild_quota(&guild_id.to_string())
+        .await?
+    {
+        check_msg(ctx.reply("Guild already uses the default quota").await);
+        return Ok(());
     }
+
+    check_msg(ctx.reply(format!("Reset the quota of {}", guild_id)).await);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_capped_to_a_message() {
+        let lines = vec![
+            "a".repeat(1000),
+            "b".repeat(800),
+            "c".repeat(200),
+            "d".to_string(),
+        ];
+        let message = join_lines_capped(&lines);
+        assert!(message.len() <= 2000);
+        assert!(message.starts_with(&lines[0]));
+        assert!(message.contains(&lines[1]));
+        assert!(message.ends_with("... and 2 more"));
+
+        let short = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(join_lines_capped(&short), "a\nb\n");
+    }
+}
@@ -1,9 +1,11 @@
-use std::sync::{Arc, Mutex};
-
-use dashmap::DashMap;
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, Mutex, Weak,
+};
 
+use anyhow::Result;
 use serenity::{
-    all::GuildId,
+    all::{ChannelId as SerenityChannelId, GuildId},
     async_trait,
     client::{Context, EventHandler},
     model::{gateway::Ready, voice::VoiceState},
@@ -12,57 +14,186 @@ use songbird::{
     events::EventHandler as VoiceEventHandler,
     id::ChannelId,
     model::payload::{ClientDisconnect, Speaking},
-    Event, EventContext, Songbird,
+    Event, EventContext,
 };
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{Instrument, Span};
 
-use crate::speech_to_text::SpeechToText;
+use crate::{
+    metrics,
+    resample::{self, MonoFrame},
+    speech_to_text::{
+        LanguageIdentifier, LanguageMode, ModelLanguage, RecognitionConfig, SpeechToText,
+    },
+    voice_activity::{VoiceActivity, VoiceActivityDetector},
+};
 
-use super::{audio_play::SongPlayer, ModelEntry, RecognitionEntries};
+use super::{
+    audio_play::SongPlayer,
+    initiate_handler,
+    listen_filter::{is_bot, ListenFilter},
+    listeners::{Connection, Listeners},
+    timers::{PendingLeaves, SessionClock},
+    worker_pool::{SpeakerQueue, Work, WorkerPool},
+    Data, ModelEntry, RecognitionEntries,
+};
 
+/// True if no one but bots is left in the channel, or the guild is gone from the cache.
 pub fn check_if_channel_empty(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> bool {
-    let someone_there = ctx
-        .cache
-        .guild(guild_id)
-        .unwrap()
+    count_humans(ctx, guild_id, channel_id.0.into()) == 0
+}
+
+/// Number of members in the channel that are not bots.
+pub fn count_humans(ctx: &Context, guild_id: GuildId, channel_id: SerenityChannelId) -> usize {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return 0;
+    };
+    guild
         .voice_states
-        .iter()
-        .filter(|(_, state)| state.user_id != ctx.cache.current_user().id)
-        .any(|(_id, state)| state.channel_id == Some(channel_id.0.into()));
-    !someone_there
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id))
+        .filter(|state| !is_bot(&ctx.cache, &guild, state.user_id))
+        .count()
 }
 
 pub struct DefaultHandler {
-    pub models: Arc<Vec<ModelEntry>>,
-    pub songbird_client: Arc<Songbird>,
+    pub data: Data,
+    pub pending_leaves: PendingLeaves,
 }
 
-#[async_trait]
-impl EventHandler for DefaultHandler {
-    async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
-    }
-    async fn voice_state_update(
+impl DefaultHandler {
+    /// Joins the channel if it's one of the guild's auto join channels and the user is the first one in it.
+    async fn auto_join(
         &self,
-        ctx: Context,
-        _: Option<VoiceState>,
-        new_voice_state: VoiceState,
-    ) {
+        ctx: &Context,
+        guild_id: GuildId,
+        voice_state: &VoiceState,
+    ) -> Result<()> {
+        let Some(channel_id) = voice_state.channel_id else {
+            return Ok(());
+        };
+        if self.data.blocked_guilds.contains(&guild_id.get()) {
+            return Ok(());
+        }
+        let is_human = ctx
+            .cache
+            .guild(guild_id)
+            .is_some_and(|guild| !is_bot(&ctx.cache, &guild, voice_state.user_id));
+        if !is_human || count_humans(ctx, guild_id, channel_id) != 1 {
+            return Ok(());
+        }
+
+        let auto_join_channels = self
+            .data
+            .database
+            .get_auto_join_channels(&guild_id.to_string())
+            .await?;
+        if !auto_join_channels.contains(&channel_id.to_string()) {
+            return Ok(());
+        }
+
+        tracing::info!("Auto joining {}:{}", guild_id, channel_id);
+        let call_handler_lock = self.data.songbird.join(guild_id, channel_id).await?;
+        initiate_handler(&self.data, ctx.cache.clone(), guild_id, call_handler_lock).await
+    }
+
+    /// Keeps the bot's current channel in the database so the session can be restored after a restart.
+    /// While shutting down the bot leaves every channel, those are kept so they can be restored.
+    async fn save_session(&self, guild_id: GuildId, channel_id: Option<SerenityChannelId>) {
+        if self.data.shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        let _task = self.data.tasks.token();
+        let guild_id = guild_id.to_string();
+        let result = match channel_id {
+            Some(channel_id) => {
+                self.data
+                    .database
+                    .save_voice_session(&guild_id, &channel_id.to_string())
+                    .await
+            }
+            None => self.data.database.remove_voice_session(&guild_id).await,
+        };
+        if let Err(err) = result {
+            tracing::error!("Failed to save voice session: {:?}", err);
+        }
+    }
+
+    /// Rejoins the channels that the bot was in before the restart, if there is still someone in them.
+    async fn restore_sessions(&self, ctx: &Context) -> Result<()> {
+        for session in self.data.database.get_voice_sessions().await? {
+            let (Ok(guild_id), Ok(channel_id)) = (
+                session.server_id.parse::<GuildId>(),
+                session.channel_id.parse::<SerenityChannelId>(),
+            ) else {
+                continue;
+            };
+            if self.data.songbird.get(guild_id).is_some() {
+                continue;
+            }
+            if count_humans(ctx, guild_id, channel_id) == 0
+                || self.data.blocked_guilds.contains(&guild_id.get())
+            {
+                self.data
+                    .database
+                    .remove_voice_session(&session.server_id)
+                    .await?;
+                continue;
+            }
+
+            tracing::info!("Restoring voice session {}:{}", guild_id, channel_id);
+            let restored = match self.data.songbird.join(guild_id, channel_id).await {
+                Ok(call_handler_lock) => {
+                    initiate_handler(&self.data, ctx.cache.clone(), guild_id, call_handler_lock)
+                        .await
+                }
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = restored {
+                tracing::error!("Failed to restore voice session {}: {:?}", guild_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_voice_state_update(&self, ctx: Context, new_voice_state: VoiceState) {
         // Basically check if the channel that the bot is in is empty everytime someone joins or leaves.
+        // Empty channels are left after a grace period, so someone reconnecting doesn't kick the bot.
         // To avoid deadlock, we have to call remove outside of the lock
         if let Some(guild_id) = new_voice_state.guild_id {
-            let remove = if let Some(call_handler_lock) = self.songbird_client.get(guild_id) {
+            if new_voice_state.user_id == ctx.cache.current_user().id {
+                self.save_session(guild_id, new_voice_state.channel_id)
+                    .await;
+            }
+            let Some(call_handler_lock) = self.data.songbird.get(guild_id) else {
+                if self.data.shutting_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Err(err) = self.auto_join(&ctx, guild_id, &new_voice_state).await {
+                    tracing::error!("Failed to auto join: {:?}", err);
+                }
+                return;
+            };
+            let remove = {
                 let call_handler = call_handler_lock.lock().await;
                 if let Some(current_channel) = call_handler.current_channel() {
                     if check_if_channel_empty(&ctx, guild_id, current_channel) {
                         tracing::info!(
-                            "Removing call_handler because the channel is empty:{}-{:?}",
+                            "Channel is empty, leaving in {:?}:{}-{:?}",
+                            self.data.timeouts.grace_period,
                             guild_id,
                             current_channel
                         );
-                        true
+                        self.pending_leaves.schedule(
+                            ctx.clone(),
+                            self.data.songbird.clone(),
+                            guild_id,
+                            self.data.timeouts.grace_period,
+                        );
                     } else {
-                        false
+                        self.pending_leaves.cancel(guild_id);
                     }
+                    false
                 } else {
                     // Remove the call_handler if it's not in a channel. This kills reconnect attemts
                     // But it's better to keep call_handlers in sync then to have a reconnect attempt
@@ -72,11 +203,10 @@ impl EventHandler for DefaultHandler {
                     );
                     true
                 }
-            } else {
-                false
             };
             if remove {
-                if let Err(err) = self.songbird_client.remove(guild_id).await {
+                self.pending_leaves.cancel(guild_id);
+                if let Err(err) = self.data.songbird.remove(guild_id).await {
                     tracing::error!("Failed to remove call_handler: {:?}", err);
                 }
             }
@@ -84,115 +214,408 @@ impl EventHandler for DefaultHandler {
     }
 }
 
+#[async_trait]
+impl EventHandler for DefaultHandler {
+    async fn ready(&self, _: Context, ready: Ready) {
+        tracing::info!("{} is connected!", ready.user.name);
+    }
+    // Voice states are not in the cache yet when `ready` fires, so restoring waits for the guilds.
+    async fn cache_ready(&self, ctx: Context, _: Vec<GuildId>) {
+        if let Err(err) = self.restore_sessions(&ctx).await {
+            tracing::error!("Failed to restore voice sessions: {:?}", err);
+        }
+    }
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        _: Option<VoiceState>,
+        new_voice_state: VoiceState,
+    ) {
+        let span = tracing::info_span!(
+            "voice_state_update",
+            guild_id = ?new_voice_state.guild_id,
+            channel_id = ?new_voice_state.channel_id,
+            user_id = %new_voice_state.user_id,
+        );
+        self.handle_voice_state_update(ctx, new_voice_state)
+            .instrument(span)
+            .await
+    }
+}
+
 #[derive(Clone)]
 pub struct VoiceHandler {
     inner: Arc<ReceiverInner>,
 }
 
+struct Listener {
+    /// Child of the handler's span, entered by the workers.
+    span: Span,
+    vad: Mutex<VoiceActivityDetector>,
+    /// Buffers of the downmixed and resampled audio, reused every tick.
+    frame: Mutex<MonoFrame>,
+    /// Only used by the worker that drains the queue, the lock is never contended.
+    recognizers: Mutex<Vec<SpeechToText>>,
+    language_mode: LanguageMode,
+    /// Only used by the worker that drains the queue, like the recognizers.
+    identifier: Mutex<LanguageIdentifier>,
+    /// The language the user set when the listener was created.
+    user_language: Option<ModelLanguage>,
+    queue: SpeakerQueue,
+}
+
+impl Listener {
+    /// Blocking, runs on the [`WorkerPool`].
+    fn listen(&self, audio: &[i16]) -> Vec<(String, ModelLanguage)> {
+        let _span = self.span.enter();
+        let mut found = Vec::new();
+        // Downmixed and resampled once, shared by the recognizers of every language.
+        let mut frame = self.frame.lock().unwrap();
+        frame.fill(audio, resample::channels_of(audio));
+        // Non-speech frames never reach the recognizers, the end of speech finalises them.
+        let activity = self.vad.lock().unwrap().detect(frame.mono());
+        let mut recognizers = self.recognizers.lock().unwrap();
+        let identifier = self.identifier.lock().unwrap();
+        let listened = recognizers
+            .iter_mut()
+            .filter(|recognizer| identifier.listens_to(recognizer.language()));
+        for recognizer in listened {
+            match activity {
+                // Long utterances are finalised while the user is still speaking.
+                VoiceActivity::Speech => {
+                    let samples = frame.at_rate(recognizer.sample_rate());
+                    metrics::FRAMES_RECOGNIZED.inc();
+                    found.extend(recognizer.listen(samples))
+                }
+                VoiceActivity::SpeechEnded => found.extend(recognizer.finalise()),
+                VoiceActivity::Silence => {}
+            }
+        }
+        drop(identifier);
+        self.identify_language(&mut recognizers, &found);
+        found
+    }
+
+    /// Blocking, runs on the [`WorkerPool`].
+    fn finalise(&self) -> Vec<(String, ModelLanguage)> {
+        let _span = self.span.enter();
+        let mut recognizers = self.recognizers.lock().unwrap();
+        let found: Vec<_> = recognizers
+            .iter_mut()
+            .filter_map(|recognizer| recognizer.finalise())
+            .collect();
+        self.identify_language(&mut recognizers, &found);
+        found
+    }
+
+    /// In identify mode the recognizers of the other languages are paused once the spoken language is known,
+    /// see [`LanguageIdentifier`]. An utterance matches a language if it triggered a sound,
+    /// otherwise if its recognizer was confident enough about the most words.
+    fn identify_language(
+        &self,
+        recognizers: &mut [SpeechToText],
+        found: &[(String, ModelLanguage)],
+    ) {
+        let LanguageMode::Identify { min_confidence } = self.language_mode else {
+            return;
+        };
+        if recognizers.len() < 2 {
+            return;
+        }
+        let scores: Vec<(ModelLanguage, f32)> = recognizers
+            .iter_mut()
+            .filter_map(|recognizer| {
+                recognizer
+                    .take_score()
+                    .map(|score| (recognizer.language(), score))
+            })
+            .collect();
+        // No utterance finished.
+        if scores.is_empty() && found.is_empty() {
+            return;
+        }
+        let matched = found.first().map(|(_, language)| *language).or_else(|| {
+            scores
+                .into_iter()
+                .filter(|(_, score)| *score >= min_confidence)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(language, _)| language)
+        });
+
+        let mut identifier = self.identifier.lock().unwrap();
+        if !identifier.utterance(matched) {
+            return;
+        }
+        match identifier.identified() {
+            Some(language) => {
+                tracing::info!(language = language.to_str(), "Identified the language");
+                // The paused recognizers start the next utterance fresh when they are resumed.
+                for recognizer in recognizers
+                    .iter_mut()
+                    .filter(|recognizer| recognizer.language() != language)
+                {
+                    let _ = recognizer.finalise();
+                    let _ = recognizer.take_score();
+                }
+            }
+            None => tracing::info!("No utterances matched, listening to every language again"),
+        }
+    }
+}
+
 struct ReceiverInner {
     models: Arc<Vec<ModelEntry>>,
-    listeners: DashMap<u32, Vec<Mutex<SpeechToText>>>,
-    user_ids: DashMap<u64, u32>,
+    listeners: Listeners<Listener>,
     player: SongPlayer,
     phrases: RecognitionEntries,
     words: RecognitionEntries,
+    filter: ListenFilter,
+    recognition: RecognitionConfig,
+    workers: Arc<WorkerPool>,
+    /// Prompts found by the workers, played by the trigger task.
+    triggers: UnboundedSender<(String, ModelLanguage)>,
+    clock: Arc<SessionClock>,
+    /// Listeners this handler added to [`metrics::LISTENERS`].
+    reported_listeners: AtomicI64,
+    /// Carries the guild id and channel id.
+    span: Span,
+}
+
+impl Drop for ReceiverInner {
+    /// A rebuilt handler only takes its own listeners out of the guild's gauge,
+    /// the series is removed once the guild has no call anymore.
+    fn drop(&mut self) {
+        let guild_id = self.player.guild_id.to_string();
+        if self.player.client.get(self.player.guild_id).is_none() {
+            let _ = metrics::LISTENERS.remove_label_values(&[&guild_id]);
+        } else {
+            metrics::LISTENERS
+                .with_label_values(&[&guild_id])
+                .sub(self.reported_listeners.load(Ordering::SeqCst));
+        }
+    }
+}
+
+pub struct WeakVoiceHandler {
+    inner: Weak<ReceiverInner>,
+}
+
+impl WeakVoiceHandler {
+    pub fn upgrade(&self) -> Option<VoiceHandler> {
+        self.inner.upgrade().map(|inner| VoiceHandler { inner })
+    }
 }
 
 impl VoiceHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         models: Arc<Vec<ModelEntry>>,
         player: SongPlayer,
         words: RecognitionEntries,
         phrases: RecognitionEntries,
+        filter: ListenFilter,
+        recognition: RecognitionConfig,
+        workers: Arc<WorkerPool>,
+        clock: Arc<SessionClock>,
     ) -> Self {
-        Self {
+        let (triggers, mut found) = mpsc::unbounded_channel();
+        let span = tracing::info_span!(
+            "voice",
+            guild_id = %player.guild_id,
+            channel_id = tracing::field::Empty,
+        );
+        let voice_handler = Self {
             inner: Arc::new(ReceiverInner {
                 models,
-                listeners: DashMap::new(),
-                user_ids: DashMap::new(),
+                listeners: Listeners::default(),
                 player,
                 words,
                 phrases,
+                filter,
+                recognition,
+                workers,
+                triggers,
+                clock,
+                reported_listeners: AtomicI64::new(0),
+                span: span.clone(),
             }),
+        };
+
+        // Ends once the handler is dropped and the queued jobs are done.
+        let weak_handler = voice_handler.downgrade();
+        tokio::spawn(
+            async move {
+                while let Some((prompt, language)) = found.recv().await {
+                    let Some(voice_handler) = weak_handler.upgrade() else {
+                        return;
+                    };
+                    voice_handler.play(&prompt, language).await;
+                }
+            }
+            .instrument(span),
+        );
+        voice_handler
+    }
+
+    pub fn record_channel(&self, channel_id: ChannelId) {
+        self.inner
+            .span
+            .record("channel_id", tracing::field::display(channel_id));
+    }
+
+    pub fn downgrade(&self) -> WeakVoiceHandler {
+        WeakVoiceHandler {
+            inner: Arc::downgrade(&self.inner),
         }
     }
 
-    fn get_speech_to_text_instances(&self) -> Vec<Mutex<SpeechToText>> {
+    pub fn clock(&self) -> &SessionClock {
+        &self.inner.clock
+    }
+
+    /// Only the user's language if they set one, otherwise every language that has sounds.
+    fn get_speech_to_text_instances(&self, user_id: u64) -> Vec<SpeechToText> {
+        let user_language = self.inner.filter.language_of(user_id);
         self.inner
             .models
             .iter()
+            .filter(|model_entry| {
+                user_language.is_none_or(|language| language == model_entry.language)
+            })
             .filter_map(|model_entry| {
                 let words = self.inner.words.filter_by_language(model_entry.language);
                 let phrases = self.inner.phrases.filter_by_language(model_entry.language);
                 if words.len() + phrases.len() == 0 {
                     None
                 } else {
-                    Some(Mutex::new(SpeechToText::new_with_grammar(
+                    Some(SpeechToText::new_with_grammar(
                         &model_entry.model,
                         model_entry.language,
+                        model_entry.sample_rate,
                         &words,
                         &phrases,
-                    )))
+                        self.inner.recognition.limits,
+                    ))
                 }
             })
             .collect()
     }
 
+    /// New recognizers are only created for users that don't have any yet, see [`Listeners::bind`].
     pub fn add_listener(&self, ssrc: u32, user_id: u64) {
-        self.inner
-            .listeners
-            .insert(ssrc, self.get_speech_to_text_instances());
-        self.inner.user_ids.insert(user_id, ssrc);
+        if !self.inner.filter.allows(user_id) {
+            return;
+        }
+        self.inner.listeners.bind(ssrc, user_id, || Listener {
+            span: tracing::info_span!(parent: &self.inner.span, "listener", user_id, ssrc),
+            vad: Mutex::new(VoiceActivityDetector::new(self.inner.recognition.vad)),
+            frame: Mutex::new(MonoFrame::default()),
+            recognizers: Mutex::new(self.get_speech_to_text_instances(user_id)),
+            language_mode: self.inner.recognition.language_mode,
+            identifier: Mutex::new(LanguageIdentifier::default()),
+            user_language: self.inner.filter.language_of(user_id),
+            queue: self.inner.workers.new_queue(),
+        });
+        self.update_listener_metric();
     }
 
     pub fn remove_listener(&self, user_id: u64) {
-        let ssrc = self.inner.user_ids.remove(&user_id);
-        if let Some(ssrc) = ssrc {
-            self.inner.listeners.remove(&ssrc.1);
-        }
+        self.inner.listeners.remove(user_id);
+        self.update_listener_metric();
     }
 
+    /// Adds the change to the guild's gauge, the old handler can still be counted in it while the call is rebuilt.
+    fn update_listener_metric(&self) {
+        let listeners = self.inner.listeners.len() as i64;
+        let reported = self
+            .inner
+            .reported_listeners
+            .swap(listeners, Ordering::SeqCst);
+        metrics::LISTENERS
+            .with_label_values(&[&self.inner.player.guild_id.to_string()])
+            .add(listeners - reported);
+    }
+
+    /// Queues the audio for recognition on the worker pool.
     pub fn listen(&self, ssrc: u32, audio: &[i16]) {
-        if let Some(listeners) = self.inner.listeners.get(&ssrc) {
-            for listener in listeners.iter() {
-                listener.lock().unwrap().listen(audio);
-            }
+        let Some((user_id, listener)) = self.inner.listeners.get(ssrc) else {
+            return;
+        };
+        // Users can opt out or get blocked while they are being listened to.
+        if self.inner.filter.is_opted_out(user_id) || self.inner.filter.is_blocked(user_id) {
+            self.remove_listener(user_id);
+            return;
+        }
+        // The recognizers follow the user's language, start over when they change it.
+        let listener = if self.inner.filter.language_of(user_id) != listener.user_language {
+            self.inner.listeners.remove(user_id);
+            self.add_listener(ssrc, user_id);
+            let Some((_, listener)) = self.inner.listeners.get(ssrc) else {
+                return;
+            };
+            listener
+        } else {
+            listener
+        };
+        if listener.queue.push_audio(audio.to_vec()) {
+            self.schedule(ssrc, listener);
         }
     }
 
-    pub fn reset_listeners(&self) {
-        self.inner.listeners.clear();
-        self.inner.user_ids.clear();
+    pub fn connection_changed(&self, connection: Connection) {
+        self.inner.listeners.connection_changed(connection);
+        self.update_listener_metric();
     }
 
-    pub async fn finalise(&self, ssrc: u32) {
-        if let Some(listeners) = self.inner.listeners.get(&ssrc) {
-            for listener in listeners.iter() {
-                let finalized = listener.lock().unwrap().finalise();
-                if let Some((finalized, language)) = finalized {
-                    self.inner.player.play_song(&finalized, language).await;
-                }
-            }
+    /// Called for the ssrcs songbird reports as silent, every tick.
+    /// Only queues finalising once per utterance, see [`SpeakerQueue::request_finalise`].
+    pub fn finalise(&self, ssrc: u32) {
+        let Some((_, listener)) = self.inner.listeners.get(ssrc) else {
+            return;
+        };
+        if listener.queue.request_finalise() {
+            self.schedule(ssrc, listener);
         }
     }
+
+    /// Drains the listener's queue on the worker pool, the found prompts go to the trigger task.
+    fn schedule(&self, ssrc: u32, listener: Arc<Listener>) {
+        let triggers = self.inner.triggers.clone();
+        self.inner.workers.execute(ssrc, move || {
+            listener.queue.drain(|work| {
+                let found = match work {
+                    Work::Audio(audio) => listener.listen(&audio),
+                    Work::Finalise => listener.finalise(),
+                };
+                for found in found {
+                    let _ = triggers.send(found);
+                }
+            });
+        });
+    }
+
+    async fn play(&self, prompt: &str, language: ModelLanguage) {
+        self.inner.clock.triggered();
+        metrics::TRIGGERS
+            .with_label_values(&[language.to_str()])
+            .inc();
+        self.inner.player.play_song(prompt, language).await;
+    }
 }
 
 #[async_trait]
 impl VoiceEventHandler for VoiceHandler {
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let _span = self.inner.span.enter();
         use EventContext as Ctx;
 
         match ctx {
             Ctx::SpeakingStateUpdate(Speaking {
                 speaking: _,
                 ssrc,
-                user_id,
+                user_id: Some(user_id),
                 ..
-            }) => {
-                if let Some(user_id) = user_id {
-                    self.add_listener(*ssrc, user_id.0)
-                }
-            }
+            }) => self.add_listener(*ssrc, user_id.0),
             Ctx::VoiceTick(tick) => {
                 for (ssrc, data) in &tick.speaking {
                     if let Some(decoded_voice) = data.decoded_voice.as_ref() {
@@ -200,7 +623,7 @@ impl VoiceEventHandler for VoiceHandler {
                     }
                 }
                 for ssrc in &tick.silent {
-                    self.finalise(*ssrc).await;
+                    self.finalise(*ssrc);
                 }
             }
 
@@ -209,14 +632,13 @@ impl VoiceEventHandler for VoiceHandler {
             }
             Ctx::DriverDisconnect(disconnect_data) => {
                 // This happens when the bot is disconnected or the bot is moved to another channel
-                self.reset_listeners();
+                self.connection_changed(Connection::Disconnected);
                 if let Some(reason) = &disconnect_data.reason {
                     tracing::debug!("Driver disconnected: {:?}", reason);
                 }
             }
             Ctx::DriverReconnect(reconnect_data) => {
-                // Idk what causes this
-                self.reset_listeners();
+                self.connection_changed(Connection::Reconnected);
                 tracing::warn!("Driver reconnected: {:?}", reconnect_data);
             }
             _ => {}
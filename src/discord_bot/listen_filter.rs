@@ -0,0 +1,115 @@
+use std::{collections::HashSet, sync::Arc};
+
+use dashmap::{DashMap, DashSet};
+use serenity::all::{Cache, Guild, GuildId, RoleId, UserId};
+
+use crate::speech_to_text::ModelLanguage;
+
+/// Decides whose audio is allowed to reach [`crate::speech_to_text::SpeechToText`], and in which languages.
+///
+/// Opted out users, blocked users and language preferences are shared between every guild and updated live by the commands.
+/// Listen roles are a snapshot taken when the [`super::events::VoiceHandler`] is created, the commands that change them rebuild it.
+/// Bots (music bots, or this bot hearing its own playback) and the ignored users are never listened to.
+pub struct ListenFilter {
+    cache: Arc<Cache>,
+    guild_id: GuildId,
+    opted_out_users: Arc<DashSet<u64>>,
+    blocked_users: Arc<DashSet<u64>>,
+    ignored_users: Arc<HashSet<u64>>,
+    /// Keyed by guild id and user id.
+    user_languages: Arc<DashMap<(u64, u64), ModelLanguage>>,
+    listen_roles: Vec<RoleId>,
+}
+
+impl ListenFilter {
+    pub fn new(
+        cache: Arc<Cache>,
+        guild_id: GuildId,
+        opted_out_users: Arc<DashSet<u64>>,
+        blocked_users: Arc<DashSet<u64>>,
+        ignored_users: Arc<HashSet<u64>>,
+        user_languages: Arc<DashMap<(u64, u64), ModelLanguage>>,
+        listen_roles: Vec<RoleId>,
+    ) -> Self {
+        Self {
+            cache,
+            guild_id,
+            opted_out_users,
+            blocked_users,
+            ignored_users,
+            user_languages,
+            listen_roles,
+        }
+    }
+
+    pub fn is_opted_out(&self, user_id: u64) -> bool {
+        self.opted_out_users.contains(&user_id)
+    }
+
+    pub fn is_blocked(&self, user_id: u64) -> bool {
+        self.blocked_users.contains(&user_id)
+    }
+
+    pub fn allows(&self, user_id: u64) -> bool {
+        !self.is_opted_out(user_id)
+            && !self.is_blocked(user_id)
+            && !self.ignored_users.contains(&user_id)
+            && !self.is_bot(user_id)
+            && self.has_listen_role(user_id)
+    }
+
+    /// The language the user speaks in the guild, `None` if every language should be listened to.
+    pub fn language_of(&self, user_id: u64) -> Option<ModelLanguage> {
+        self.user_languages
+            .get(&(self.guild_id.get(), user_id))
+            .map(|language| *language)
+    }
+
+    fn is_bot(&self, user_id: u64) -> bool {
+        let user_id = UserId::new(user_id);
+        match self.cache.guild(self.guild_id) {
+            Some(guild) => is_bot(&self.cache, &guild, user_id),
+            None => is_bot_user(&self.cache, user_id),
+        }
+    }
+
+    /// If the guild didn't set any listen roles, everyone is listened to.
+    /// Otherwise the member has to be in the cache with one of the roles.
+    fn has_listen_role(&self, user_id: u64) -> bool {
+        if self.listen_roles.is_empty() {
+            return true;
+        }
+
+        let user_id = UserId::new(user_id);
+        let Some(guild) = self.cache.guild(self.guild_id) else {
+            return false;
+        };
+        let roles = guild
+            .members
+            .get(&user_id)
+            .map(|member| &member.roles)
+            .or_else(|| {
+                guild
+                    .voice_states
+                    .get(&user_id)
+                    .and_then(|voice_state| voice_state.member.as_ref())
+                    .map(|member| &member.roles)
+            });
+
+        roles.is_some_and(|roles| roles.iter().any(|role| self.listen_roles.contains(role)))
+    }
+}
+
+/// True for this bot and other bots. Users missing from the cache are assumed to be humans.
+pub(crate) fn is_bot(cache: &Cache, guild: &Guild, user_id: UserId) -> bool {
+    is_bot_user(cache, user_id)
+        || guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|voice_state| voice_state.member.as_ref())
+            .is_some_and(|member| member.user.bot)
+}
+
+fn is_bot_user(cache: &Cache, user_id: UserId) -> bool {
+    user_id == cache.current_user().id || cache.user(user_id).is_some_and(|user| user.bot)
+}
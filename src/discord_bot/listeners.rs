@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+
+/// Which user each ssrc belongs to, and the listener that keeps their recognition state.
+///
+/// Kept apart from the recognizers so the bookkeeping doesn't need a cache or Vosk models.
+pub struct Listeners<L> {
+    listeners: DashMap<u32, Bound<L>>,
+    user_ids: DashMap<u64, u32>,
+}
+
+struct Bound<L> {
+    user_id: u64,
+    listener: Arc<L>,
+}
+
+/// Changes of the voice connection that matter to the listeners.
+#[derive(Clone, Copy, Debug)]
+pub enum Connection {
+    /// The bot left or was moved, every ssrc is stale.
+    Disconnected,
+    /// The connection dropped and came back in the same channel.
+    /// Listeners are kept, if the ssrcs changed they are rebound in [`Listeners::bind`].
+    Reconnected,
+}
+
+impl<L> Default for Listeners<L> {
+    fn default() -> Self {
+        Self {
+            listeners: DashMap::new(),
+            user_ids: DashMap::new(),
+        }
+    }
+}
+
+impl<L> Listeners<L> {
+    /// Speaking updates can come more than once for the same user, and the ssrc can change after a reconnect.
+    /// The existing listener is moved to the new ssrc so buffered speech isn't lost.
+    /// `create` is only called for users that don't have one yet.
+    pub fn bind(&self, ssrc: u32, user_id: u64, create: impl FnOnce() -> L) {
+        let previous_ssrc = self.user_ids.insert(user_id, ssrc);
+        let listener = previous_ssrc
+            .and_then(|previous_ssrc| {
+                self.listeners
+                    .remove_if(&previous_ssrc, |_, bound| bound.user_id == user_id)
+            })
+            .map(|(_, bound)| bound.listener)
+            .unwrap_or_else(|| Arc::new(create()));
+
+        // Discord reused the ssrc of someone else, that user will get a new one when they speak again.
+        if let Some(replaced) = self.listeners.insert(ssrc, Bound { user_id, listener }) {
+            if replaced.user_id != user_id {
+                self.user_ids
+                    .remove_if(&replaced.user_id, |_, replaced_ssrc| *replaced_ssrc == ssrc);
+            }
+        }
+    }
+
+    /// The user and the listener of the ssrc.
+    pub fn get(&self, ssrc: u32) -> Option<(u64, Arc<L>)> {
+        self.listeners
+            .get(&ssrc)
+            .map(|bound| (bound.user_id, bound.listener.clone()))
+    }
+
+    pub fn ssrc_of(&self, user_id: u64) -> Option<u32> {
+        self.user_ids.get(&user_id).map(|ssrc| *ssrc)
+    }
+
+    pub fn remove(&self, user_id: u64) {
+        if let Some((_, ssrc)) = self.user_ids.remove(&user_id) {
+            self.listeners.remove(&ssrc);
+        }
+    }
+
+    pub fn connection_changed(&self, connection: Connection) {
+        match connection {
+            Connection::Disconnected => {
+                self.listeners.clear();
+                self.user_ids.clear();
+            }
+            Connection::Reconnected => {}
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_the_same_user_keeps_the_listener() {
+        let listeners = Listeners::default();
+        listeners.bind(1, 10, || "first");
+        let (_, first) = listeners.get(1).unwrap();
+
+        listeners.bind(2, 10, || "second");
+
+        assert!(listeners.get(1).is_none());
+        let (user_id, rebound) = listeners.get(2).unwrap();
+        assert_eq!(user_id, 10);
+        assert!(Arc::ptr_eq(&first, &rebound));
+        assert_eq!(listeners.ssrc_of(10), Some(2));
+        assert_eq!(listeners.len(), 1);
+    }
+
+    #[test]
+    fn reusing_the_ssrc_of_another_user_evicts_them() {
+        let listeners = Listeners::default();
+        listeners.bind(1, 10, || "first");
+
+        listeners.bind(1, 20, || "second");
+
+        let (user_id, listener) = listeners.get(1).unwrap();
+        assert_eq!(user_id, 20);
+        assert_eq!(*listener, "second");
+        assert_eq!(listeners.ssrc_of(10), None);
+        assert_eq!(listeners.ssrc_of(20), Some(1));
+
+        // The evicted user gets a new listener once they speak again.
+        listeners.bind(3, 10, || "third");
+        assert_eq!(*listeners.get(3).unwrap().1, "third");
+        assert_eq!(listeners.len(), 2);
+    }
+
+    #[test]
+    fn reconnecting_keeps_the_listeners() {
+        let listeners = Listeners::default();
+        listeners.bind(1, 10, || "first");
+        let (_, first) = listeners.get(1).unwrap();
+
+        listeners.connection_changed(Connection::Reconnected);
+        assert!(Arc::ptr_eq(&first, &listeners.get(1).unwrap().1));
+
+        // Discord can hand out new ssrcs after a reconnect.
+        listeners.bind(5, 10, || "second");
+        assert!(Arc::ptr_eq(&first, &listeners.get(5).unwrap().1));
+
+        listeners.connection_changed(Connection::Disconnected);
+        assert!(listeners.is_empty());
+        assert_eq!(listeners.ssrc_of(10), None);
+    }
+}
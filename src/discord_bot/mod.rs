@@ -4,11 +4,18 @@
 //! - Join is handled in [`commands::join()`]. This adds a new [`events::VoiceHandler`] handler
 //!   to the [`songbird::handler::Call`] handler which is managed by [`songbird::manager::Songbird`].
 //!
+//! - Auto join works the same way, but it's started from `voice_state_update` event in [`events::DefaultHandler`]
+//!   when the first member enters one of the guild's auto join channels. Both use [`initiate_handler()`].
+//!
 //! - If [`commands::join()`] command is used when bot is already connected to a channel
 //!   old [`events::VoiceHandler`] handler is removed and new handler is added to the [`songbird::handler::Call`].
 //!
 //! - If bot is dragged into a new channel (aka channel movement), then joining a new channel doesn't replace the [`events::VoiceHandler`].
-//!   Instead the recognizers and user id's that are in the [`events::VoiceHandler`] gets reset with [`events::VoiceHandler::reset_listeners()`]
+//!   Instead the recognizers and user id's that are in the [`events::VoiceHandler`] gets reset with [`events::VoiceHandler::connection_changed()`]
+//!
+//! - If the voice connection drops and songbird reconnects to the same channel (`DriverReconnect`), nothing is reset.
+//!   Recognizers are kept by user id. When a `SpeakingStateUpdate` arrives with a new ssrc for a known user,
+//!   [`events::VoiceHandler::add_listener()`] moves that user's recognizers to the new ssrc instead of creating new ones.
 //!
 //! - [`commands::leave()`] command only disconnects the bot. It doesn't drop the [`songbird::handler::Call`]
 //!   hence [`events::VoiceHandler`] also stays.
@@ -17,34 +24,81 @@
 //!
 //! - [`songbird::handler::Call`] is dropped by checking if the bot is alone or not in a channel when
 //!   `voice_state_update` event is fired in [`events::DefaultHandler`].
-//!   If that's the case [`songbird::handler::Call`] is removed by calling [`songbird::manager::Songbird::remove()`].
+//!   If that's the case a leave is scheduled with [`timers::PendingLeaves`]. If the channel is still empty after the grace period,
+//!   [`songbird::handler::Call`] is removed by calling [`songbird::manager::Songbird::remove()`].
+//!   Someone joining the channel in the meantime cancels the leave.
 //!   This also drops the [`events::VoiceHandler`].
 //!
+//! - Recognition doesn't run on songbird's event task. [`events::VoiceHandler`] queues the audio on the shared
+//!   [`worker_pool::WorkerPool`] and plays the prompts the workers send back.
+//!   Every speaker has a bounded queue, its oldest frames are dropped if the workers fall behind.
+//!
+//! - [`timers::watch_session()`] removes the [`songbird::handler::Call`] the same way when the session is too long or idle.
+//!   It holds a weak reference to the [`events::VoiceHandler`], so it stops when the handler is dropped.
+//!
+//! - The bot's own `voice_state_update` events keep its current channel in the database.
+//!   After a restart, `cache_ready` in [`events::DefaultHandler`] rejoins those channels if there is still someone in them.
+//!   On shutdown every [`songbird::handler::Call`] is removed, but the sessions are kept in the database for this.
+//!
 //! Sounds robust. Until something will eventually break as always.
 
-use self::{audio_play::SongPlayer, events::VoiceHandler};
-use std::{collections::HashSet, env, sync::Arc};
+use self::{
+    audio_play::SongPlayer,
+    events::VoiceHandler,
+    listen_filter::ListenFilter,
+    quota::QuotaLocks,
+    timers::{PendingLeaves, SessionClock, SessionClocks, VoiceTimeouts},
+    worker_pool::WorkerPool,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
-use poise::{Framework, FrameworkOptions, PrefixFrameworkOptions};
+use anyhow::{Context as _, Result};
+use dashmap::{DashMap, DashSet};
+use poise::{ChoiceParameter, Framework, FrameworkOptions, PrefixFrameworkOptions};
 
-use serenity::all::{GatewayIntents, GuildId};
-use songbird::{driver::DecodeMode, Config, Songbird};
+use serenity::all::{Cache, GatewayIntents, GuildId, Http, ShardManager, UserId};
+use songbird::{driver::DecodeMode, Call, Config, CoreEvent, Songbird};
+use tokio::sync::Mutex;
+use tokio_util::task::TaskTracker;
 
 use vosk::Model;
 
 use crate::{
-    database::Database, discord_bot::events::DefaultHandler, speech_to_text::ModelLanguage,
+    config::{self, ModelSettings},
+    database::Database,
+    discord_bot::{blobs::BlobStore, events::DefaultHandler},
+    http_server::{self, HttpState},
+    logging,
+    speech_to_text::{model_sample_rate, ModelLanguage, RecognitionConfig},
 };
 
+pub mod archive;
 pub mod audio_play;
+pub mod blobs;
 pub mod commands;
 pub mod events;
+pub mod listen_filter;
+pub mod listeners;
+pub mod quota;
+pub mod sounds;
+pub mod timers;
+pub mod transcode;
+pub mod worker_pool;
 
 pub struct Sound {
     name: String,
     recognition_type: RecognitionType,
     language: ModelLanguage,
-    path: String,
+    path: PathBuf,
 }
 
 #[derive(Default)]
@@ -61,21 +115,26 @@ impl SoundBoard {
         name: &str,
         recognition_type: RecognitionType,
         language: ModelLanguage,
-        path: &str,
+        path: &Path,
     ) -> Self {
         self.sounds.push(Sound {
             name: name.to_string(),
             recognition_type,
             language,
-            path: path.to_string(),
+            path: path.to_path_buf(),
         });
         self
     }
-    pub async fn get_player(&self, client: Arc<Songbird>, guild_id: GuildId) -> SongPlayer {
-        let mut player = SongPlayer::new(client, guild_id);
+    pub async fn get_player(
+        &self,
+        client: Arc<Songbird>,
+        guild_id: GuildId,
+        bitrate: i32,
+    ) -> SongPlayer {
+        let mut player = SongPlayer::new(client, guild_id, bitrate);
         for sound in &self.sounds {
             player
-                .add_song(&sound.name, sound.language, &sound.path)
+                .add_song(&sound.name, sound.language, sound.path.clone())
                 .await;
         }
         player
@@ -122,10 +181,23 @@ impl SoundBoard {
         &self,
         models: Arc<Vec<ModelEntry>>,
         player: SongPlayer,
+        filter: ListenFilter,
+        recognition: RecognitionConfig,
+        workers: Arc<WorkerPool>,
+        clock: Arc<SessionClock>,
     ) -> VoiceHandler {
         let phrases = self.get_phrases();
         let words = self.get_words();
-        VoiceHandler::new(models, player, words, phrases)
+        VoiceHandler::new(
+            models,
+            player,
+            words,
+            phrases,
+            filter,
+            recognition,
+            workers,
+            clock,
+        )
     }
 }
 
@@ -160,20 +232,191 @@ impl RecognitionEntries {
 pub struct ModelEntry {
     pub model: Model,
     pub language: ModelLanguage,
+    /// Rate the model was trained on, the audio is resampled to it before recognition.
+    pub sample_rate: u32,
+}
+
+impl ModelEntry {
+    pub fn load(settings: &ModelSettings) -> Result<Self> {
+        let model = Model::new(&settings.path)
+            .with_context(|| format!("Could not load the model at {}", settings.path))?;
+        Ok(Self {
+            model,
+            language: settings.language,
+            sample_rate: model_sample_rate(&settings.path),
+        })
+    }
+}
+
+/// The loaded models, shared by every guild.
+///
+/// Reloading swaps the whole list. Calls keep the models their [`events::VoiceHandler`] was built with
+/// until their handler is replaced.
+#[derive(Clone)]
+pub struct ModelStore {
+    inner: Arc<RwLock<Arc<Vec<ModelEntry>>>>,
 }
 
+impl ModelStore {
+    pub fn new(models: Vec<ModelEntry>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Arc::new(models))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Vec<ModelEntry>> {
+        self.inner.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, models: Vec<ModelEntry>) {
+        *self.inner.write().unwrap() = Arc::new(models);
+    }
+}
+
+#[derive(Clone)]
 pub struct Data {
+    config: Arc<config::Config>,
     songbird: Arc<songbird::Songbird>,
-    models: Arc<Vec<ModelEntry>>,
+    models: ModelStore,
     database: Arc<Database>,
+    blobs: Arc<BlobStore>,
+    opted_out_users: Arc<DashSet<u64>>,
+    /// Guilds and users the owners blocked from using the bot.
+    blocked_guilds: Arc<DashSet<u64>>,
+    blocked_users: Arc<DashSet<u64>>,
+    /// Keyed by guild id and user id.
+    user_languages: Arc<DashMap<(u64, u64), ModelLanguage>>,
+    ignored_users: Arc<HashSet<u64>>,
+    timeouts: VoiceTimeouts,
+    sessions: SessionClocks,
+    quota_locks: QuotaLocks,
+    recognition: RecognitionConfig,
+    /// Shared by every guild.
+    workers: Arc<WorkerPool>,
+    /// Uploads and database writes that should finish before the bot shuts down.
+    tasks: TaskTracker,
+    shutting_down: Arc<AtomicBool>,
+}
+
+/// Blocked users and guilds can't use any command. Owners are never blocked.
+/// No command starts once the bot is shutting down, the running ones are waited for.
+async fn is_allowed(ctx: Context<'_>) -> Result<bool> {
+    if ctx.data().shutting_down.load(Ordering::SeqCst) {
+        check_msg(ctx.reply("Restarting, try again in a moment.").await);
+        return Ok(false);
+    }
+    if ctx.framework().options().owners.contains(&ctx.author().id) {
+        return Ok(true);
+    }
+    let data = ctx.data();
+    Ok(!data.blocked_users.contains(&ctx.author().id.get())
+        && !ctx
+            .guild_id()
+            .is_some_and(|guild_id| data.blocked_guilds.contains(&guild_id.get())))
 }
 
+/// How long the shutdown waits for the running tasks.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
 
-pub async fn run() {
-    tracing_subscriber::fmt::init();
+/// Builds the [`events::VoiceHandler`] from the guild's sounds and settings,
+/// and replaces the handlers of the [`songbird::handler::Call`] with it.
+pub async fn initiate_handler(
+    data: &Data,
+    cache: Arc<Cache>,
+    guild_id: GuildId,
+    call_handler_lock: Arc<Mutex<Call>>,
+) -> Result<()> {
+    let sounds = data
+        .database
+        .get_sounds(guild_id.to_string().as_str())
+        .await?;
+
+    let sound_board = sounds
+        .into_iter()
+        .fold(SoundBoard::new(), |sound_board, sound| {
+            let recognition_type = if sound.prompt.split_whitespace().count() > 1 {
+                RecognitionType::PHRASE
+            } else {
+                RecognitionType::WORD
+            };
+            sound_board.add_song(
+                &sound.prompt,
+                recognition_type,
+                ModelLanguage::from_name(&sound.language).unwrap(),
+                &data.config.storage.song_path(&sound.file_name),
+            )
+        });
+
+    let listen_roles = data
+        .database
+        .get_listen_roles(guild_id.to_string().as_str())
+        .await?
+        .into_iter()
+        .filter_map(|role_id| role_id.parse().ok())
+        .collect();
+    let filter = ListenFilter::new(
+        cache,
+        guild_id,
+        data.opted_out_users.clone(),
+        data.blocked_users.clone(),
+        data.ignored_users.clone(),
+        data.user_languages.clone(),
+        listen_roles,
+    );
+
+    let player = sound_board
+        .get_player(
+            data.songbird.clone(),
+            guild_id,
+            data.config.playback.bitrate,
+        )
+        .await;
+    let models = data.models.current();
+
+    let voice_handler = sound_board.get_voice_handler(
+        models,
+        player,
+        filter,
+        data.recognition,
+        data.workers.clone(),
+        data.sessions.of_call(guild_id, &call_handler_lock),
+    );
+    timers::watch_session(
+        &voice_handler,
+        data.songbird.clone(),
+        guild_id,
+        data.timeouts,
+    );
+
+    {
+        let mut call_handler = call_handler_lock.lock().await;
+
+        call_handler.remove_all_global_events();
+        if let Some(channel_id) = call_handler.current_channel() {
+            voice_handler.record_channel(channel_id);
+        }
+
+        call_handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), voice_handler.clone());
+        call_handler.add_global_event(CoreEvent::ClientDisconnect.into(), voice_handler.clone());
+        call_handler.add_global_event(CoreEvent::VoiceTick.into(), voice_handler.clone());
+        call_handler.add_global_event(CoreEvent::DriverDisconnect.into(), voice_handler.clone());
+        call_handler.add_global_event(CoreEvent::DriverReconnect.into(), voice_handler);
+    }
+    Ok(())
+}
 
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+pub async fn run() {
+    // Logging is set up from the config, so a broken config can only be printed.
+    let config = match config::Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("{:#}", err);
+            process::exit(1);
+        }
+    };
+    logging::init(&config.logging);
 
     let songbird_config = Config::default().decode_mode(DecodeMode::Decode);
     let songbird_client = Songbird::serenity_from_config(songbird_config);
@@ -190,73 +433,212 @@ pub async fn run() {
             commands::add_sound(),
             commands::remove_sound(),
             commands::list_sounds(),
+            commands::quota(),
+            commands::export(),
+            commands::catalog(),
+            commands::import(),
+            commands::privacy(),
+            commands::language(),
+            commands::listen_roles(),
+            commands::auto_join(),
+            commands::admin(),
         ],
+        command_check: Some(|ctx| Box::pin(is_allowed(ctx))),
         prefix_options: PrefixFrameworkOptions {
-            prefix: Some(".".to_string()),
+            prefix: Some(config.discord.prefix.clone()),
             ..Default::default()
         },
-        owners: HashSet::from([env::var("OWNER_ID")
-            .expect("Expected an owner id")
-            .parse()
-            .unwrap()]),
+        owners: config
+            .discord
+            .owners
+            .iter()
+            .map(|&owner| UserId::new(owner))
+            .collect(),
         ..Default::default()
     };
 
-    let models = vec![
-        ModelEntry {
-            model: Model::new("vosk/model/turkish").expect("Could not create the model"),
-            language: ModelLanguage::TURKISH,
-        },
-        ModelEntry {
-            model: Model::new("vosk/model/dutch").expect("Could not create the model"),
-            language: ModelLanguage::DUTCH,
-        },
-        ModelEntry {
-            model: Model::new("vosk/model/english").expect("Could not create the model"),
-            language: ModelLanguage::ENGLISH,
-        },
-    ];
-    let models = Arc::new(models);
-    let models_clone = models.clone();
-
-    let songbird_client_clone = songbird_client.clone();
-    let framework = Framework::new(framework_options, |_, _, _| {
-        Box::pin(async {
-            Ok(Data {
-                songbird: songbird_client_clone,
-                models: models_clone,
-                database: Arc::new(Database::new().await.unwrap()),
-            })
+    let models = config
+        .models
+        .iter()
+        .map(ModelEntry::load)
+        .collect::<Result<_>>()
+        .expect("Could not load the models");
+    let models = ModelStore::new(models);
+
+    // User ids that are never listened to. E.g. music bots that are not flagged as bots.
+    let ignored_users: HashSet<u64> = config.voice.ignored_users.iter().copied().collect();
+    let ignored_users = Arc::new(ignored_users);
+    let timeouts = VoiceTimeouts::from_config(&config.voice);
+
+    let database = Database::new(&config.database.url)
+        .await
+        .expect("Could not connect to the database");
+    let database = Arc::new(database);
+    let blobs = Arc::new(BlobStore::new(database.clone(), &config.storage));
+    if let Err(err) = blobs.migrate().await {
+        tracing::error!("Failed to move the sounds to the blobs: {:?}", err);
+    }
+    if let Err(err) = quota::backfill_sizes(&database, &config.storage).await {
+        tracing::error!("Failed to record the sizes of the sounds: {:?}", err);
+    }
+    let opted_out_users = database
+        .get_opted_out_users()
+        .await
+        .expect("Could not get the opted out users")
+        .into_iter()
+        .filter_map(|user_id| user_id.parse().ok())
+        .collect();
+    let blocked_guilds = database
+        .get_blocked_guilds()
+        .await
+        .expect("Could not get the blocked guilds")
+        .into_iter()
+        .filter_map(|guild_id| guild_id.parse().ok())
+        .collect();
+    let blocked_users = database
+        .get_blocked_users()
+        .await
+        .expect("Could not get the blocked users")
+        .into_iter()
+        .filter_map(|user_id| user_id.parse().ok())
+        .collect();
+    let user_languages = database
+        .get_user_languages()
+        .await
+        .expect("Could not get the user languages")
+        .into_iter()
+        .filter_map(|user_language| {
+            Some((
+                (
+                    user_language.server_id.parse().ok()?,
+                    user_language.user_id.parse().ok()?,
+                ),
+                ModelLanguage::from_name(&user_language.language)?,
+            ))
         })
+        .collect();
+
+    let data = Data {
+        config: config.clone(),
+        songbird: songbird_client.clone(),
+        models,
+        database,
+        blobs,
+        opted_out_users: Arc::new(opted_out_users),
+        blocked_guilds: Arc::new(blocked_guilds),
+        blocked_users: Arc::new(blocked_users),
+        user_languages: Arc::new(user_languages),
+        ignored_users,
+        timeouts,
+        sessions: SessionClocks::default(),
+        quota_locks: QuotaLocks::default(),
+        recognition: RecognitionConfig::from_config(&config.recognition),
+        workers: WorkerPool::from_config(&config.recognition),
+        tasks: TaskTracker::new(),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+    };
+
+    let shutdown_data = data.clone();
+    let data_clone = data.clone();
+    let framework = Framework::new(framework_options, move |_, _, _| {
+        Box::pin(async move { Ok(data_clone) })
     });
 
-    let songbird_client_clone = songbird_client.clone();
     let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
 
-    let mut client = serenity::Client::builder(&token, intents)
+    let mut client = serenity::Client::builder(&config.discord.token, intents)
         .voice_manager_arc(songbird_client)
         .event_handler(DefaultHandler {
-            models,
-            songbird_client: songbird_client_clone,
+            data,
+            pending_leaves: PendingLeaves::default(),
         })
         .framework(framework)
         .await
         .expect("Err creating client");
 
-    tokio::spawn(async move {
+    let http = client.http.clone();
+    let shard_manager = client.shard_manager.clone();
+    http_server::spawn(
+        config.http.addr,
+        HttpState {
+            songbird: shutdown_data.songbird.clone(),
+            shard_manager: shard_manager.clone(),
+            database: shutdown_data.database.clone(),
+            models: shutdown_data.models.clone(),
+        },
+    );
+    let client_handle = tokio::spawn(async move {
         let _ = client
             .start()
             .await
-            .map_err(|why| println!("Client ended: {:?}", why));
+            .map_err(|why| tracing::error!("Client ended: {:?}", why));
     });
 
-    let _signal_err = tokio::signal::ctrl_c().await;
-    println!("Received Ctrl-C, shutting down.");
+    wait_for_shutdown_signal().await;
+    shutdown(&shutdown_data, &http, &shard_manager).await;
+    let _ = client_handle.await;
+}
+
+/// Waits for Ctrl-C, or SIGTERM which is what `docker stop` sends.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => tracing::info!("Received Ctrl-C, shutting down."),
+            _ = sigterm.recv() => tracing::info!("Received SIGTERM, shutting down."),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _signal_err = tokio::signal::ctrl_c().await;
+        tracing::info!("Received Ctrl-C, shutting down.");
+    }
+}
+
+/// Stops new commands, tells the users in the calls that the bot is restarting and leaves the calls.
+/// Then stops the shards and waits for the running uploads and database writes.
+///
+/// Voice sessions are kept in the database so they are restored when the bot starts again.
+async fn shutdown(data: &Data, http: &Http, shard_manager: &ShardManager) {
+    data.shutting_down.store(true, Ordering::SeqCst);
+
+    let calls: Vec<_> = data.songbird.iter().collect();
+    for (guild_id, call_handler_lock) in calls {
+        let channel_id = call_handler_lock.lock().await.current_channel();
+        if let Some(channel_id) = channel_id {
+            check_msg(
+                serenity::all::ChannelId::new(channel_id.0.get())
+                    .say(http, "Restarting, I will be back in a moment.")
+                    .await,
+            );
+        }
+        if let Err(err) = data.songbird.remove(guild_id).await {
+            tracing::error!("Failed to remove call_handler: {:?}", err);
+        }
+    }
+
+    shard_manager.shutdown_all().await;
+
+    data.tasks.close();
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, data.tasks.wait())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Gave up waiting for {} tasks after {:?}",
+            data.tasks.len(),
+            SHUTDOWN_TIMEOUT
+        );
+    }
+
+    data.database.close().await;
 }
 
 /// Checks that a message successfully sent; if not, then logs why to stdout.
 fn check_msg<T>(result: serenity::Result<T>) {
     if let Err(why) = result {
-        println!("Error sending message: {:?}", why);
+        tracing::error!("Error sending message: {:?}", why);
     }
 }
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use dashmap::DashMap;
+use tokio::{
+    fs,
+    sync::{Mutex, OwnedMutexGuard},
+};
+
+use crate::{config::StorageSettings, database::Database};
+
+/// Limits on the sounds of a guild. Owners can override the configured defaults per guild.
+pub struct Quota {
+    pub max_sounds: u64,
+    pub max_bytes: u64,
+    /// True if an owner set the limits of this guild.
+    pub custom: bool,
+}
+
+/// Serializes the quota check and the insert of a guild's sounds, so parallel uploads can't exceed the quota together.
+#[derive(Clone, Default)]
+pub struct QuotaLocks {
+    inner: Arc<DashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl QuotaLocks {
+    /// Hold it from reading the [`Usage`] until the sound is added.
+    pub async fn lock(&self, server_id: &str) -> OwnedMutexGuard<()> {
+        let lock = self.inner.entry(server_id.to_string()).or_default().clone();
+        lock.lock_owned().await
+    }
+}
+
+/// Sizes are the uploaded ones, not what the stored file takes after encoding.
+pub struct Usage {
+    pub sounds: u64,
+    pub bytes: u64,
+}
+
+impl Quota {
+    pub async fn of_guild(
+        database: &Database,
+        storage: &StorageSettings,
+        server_id: &str,
+    ) -> Result<Self> {
+        let quota = database.get_guild_quota(server_id).await?;
+        Ok(Self {
+            max_sounds: quota
+                .as_ref()
+                .and_then(|quota| quota.max_sounds)
+                .map_or(storage.max_sounds_per_guild, |max| max as u64),
+            max_bytes: quota
+                .as_ref()
+                .and_then(|quota| quota.max_bytes)
+                .map_or(storage.max_bytes_per_guild, |max| max as u64),
+            custom: quota.is_some(),
+        })
+    }
+
+    /// Why a sound of `bytes` can't be added, `None` if it fits.
+    pub fn rejects(&self, usage: &Usage, bytes: u64) -> Option<String> {
+        if usage.sounds >= self.max_sounds {
+            return Some(format!(
+                "This server reached its limit of {} sounds. Remove some to add new ones.",
+                self.max_sounds
+            ));
+        }
+        if usage.bytes + bytes > self.max_bytes {
+            return Some(format!(
+                "This server doesn't have enough storage left, {} of {} is used.",
+                format_bytes(usage.bytes),
+                format_bytes(self.max_bytes)
+            ));
+        }
+        None
+    }
+}
+
+impl Usage {
+    pub async fn of_guild(database: &Database, server_id: &str) -> Result<Self> {
+        let usage = database.get_sound_usage(server_id).await?;
+        Ok(Self {
+            sounds: usage.sounds as u64,
+            bytes: usage.bytes as u64,
+        })
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Records the sizes of the sounds that were added before the size column existed.
+/// Sounds whose file is missing are left at 0.
+pub async fn backfill_sizes(database: &Database, storage: &StorageSettings) -> Result<()> {
+    let file_names = database.get_unsized_sound_files().await?;
+    if file_names.is_empty() {
+        return Ok(());
+    }
+    let mut filled = 0;
+    for file_name in &file_names {
+        match fs::metadata(storage.song_path(file_name)).await {
+            Ok(metadata) => {
+                database
+                    .set_sound_size(file_name, metadata.len() as i64)
+                    .await?;
+                filled += 1;
+            }
+            Err(err) => tracing::warn!("Could not read the size of {}: {:?}", file_name, err),
+        }
+    }
+    tracing::info!("Recorded the sizes of {} sounds", filled);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+use crate::{config::StorageSettings, speech_to_text::ModelLanguage};
+
+use super::Data;
+
+/// Prompts are stored in a `VARCHAR(255)`.
+const MAX_PROMPT_LENGTH: usize = 255;
+
+/// Checks a sound before it is stored. Used by every command that adds sounds, so they all accept the same files.
+/// Returns the trimmed prompt, or why the sound was rejected.
+pub fn validate<'a>(
+    storage: &StorageSettings,
+    prompt: &'a str,
+    content: &[u8],
+) -> Result<&'a str, String> {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return Err("Prompt cannot be empty".to_string());
+    }
+    if prompt.len() > MAX_PROMPT_LENGTH {
+        return Err(format!(
+            "Prompt is too long. Max {} characters.",
+            MAX_PROMPT_LENGTH
+        ));
+    }
+    if content.len() as u64 > storage.max_upload_bytes {
+        return Err(format!(
+            "File size too large. Max {}kb.",
+            storage.max_upload_bytes / 1024
+        ));
+    }
+    if !is_audio(content) {
+        return Err("Only audio files are supported".to_string());
+    }
+    Ok(prompt)
+}
+
+/// True if symphonia, with the formats songbird adds to it, finds an audio track in it.
+/// Archives exported without the originals contain the stored Opus files.
+fn is_audio(content: &[u8]) -> bool {
+    let source =
+        MediaSourceStream::new(Box::new(Cursor::new(content.to_vec())), Default::default());
+    songbird::input::codecs::PROBE
+        .format(
+            &Hint::new(),
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .is_ok_and(|probed| probed.format.default_track().is_some())
+}
+
+/// Adds a validated sound to the guild. Identical files are only stored once, see [`super::blobs::BlobStore`].
+/// Returns false if the guild already has a sound with the prompt and language.
+pub async fn store(
+    data: &Data,
+    server_id: &str,
+    prompt: &str,
+    language: ModelLanguage,
+    content: &[u8],
+) -> Result<bool> {
+    let content_hash = data.blobs.put(content).await?;
+    // The uploaded size, the same one the quota was checked against.
+    let added = data
+        .database
+        .add_sound(
+            server_id,
+            prompt,
+            language.to_str(),
+            &content_hash,
+            content.len() as i64,
+        )
+        .await;
+    if !matches!(added, Ok(true)) {
+        let _ = data.blobs.release(&content_hash).await;
+    }
+    added
+}
@@ -0,0 +1,199 @@
+use std::{
+    sync::{Arc, Mutex as StdMutex, Weak},
+    time::{Duration, Instant},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use serenity::{all::GuildId, client::Context};
+use songbird::{Call, Songbird};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::config::VoiceSettings;
+
+use super::events::{check_if_channel_empty, VoiceHandler};
+
+/// How often the session limits are checked.
+const WATCH_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Copy)]
+pub struct VoiceTimeouts {
+    /// How long the bot waits in an empty channel before leaving.
+    pub grace_period: Duration,
+    /// Leave after being in a call for this long, no matter what.
+    pub max_session: Option<Duration>,
+    /// Leave if no sound was triggered for this long.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl VoiceTimeouts {
+    /// The session limits are disabled if they are not set.
+    pub fn from_config(settings: &VoiceSettings) -> Self {
+        Self {
+            grace_period: settings.grace_period(),
+            max_session: settings.max_session_secs.map(Duration::from_secs),
+            idle_timeout: settings.idle_timeout_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+/// When a call started and when it last triggered a sound.
+/// Kept per call, so rebuilding the [`VoiceHandler`] doesn't restart the session limits.
+pub struct SessionClock {
+    started_at: Instant,
+    last_trigger: StdMutex<Instant>,
+}
+
+impl SessionClock {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_trigger: StdMutex::new(Instant::now()),
+        }
+    }
+
+    pub fn session_length(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Time since the last sound was triggered, or since the session started.
+    pub fn idle_time(&self) -> Duration {
+        self.last_trigger.lock().unwrap().elapsed()
+    }
+
+    pub fn triggered(&self) {
+        *self.last_trigger.lock().unwrap() = Instant::now();
+    }
+}
+
+/// The call a clock belongs to, and the clock.
+type CallClock = (Weak<Mutex<Call>>, Arc<SessionClock>);
+
+/// The [`SessionClock`] of every guild's call, by guild.
+#[derive(Clone, Default)]
+pub struct SessionClocks {
+    inner: Arc<DashMap<GuildId, CallClock>>,
+}
+
+impl SessionClocks {
+    /// The clock of the call, a new one if the guild's previous call was removed in the meantime.
+    pub fn of_call(
+        &self,
+        guild_id: GuildId,
+        call_handler_lock: &Arc<Mutex<Call>>,
+    ) -> Arc<SessionClock> {
+        let mut entry = self.inner.entry(guild_id).or_insert_with(|| {
+            (
+                Arc::downgrade(call_handler_lock),
+                Arc::new(SessionClock::new()),
+            )
+        });
+        let (call, clock) = entry.value_mut();
+        // The weak pointer keeps the allocation, a new call can't get the same address.
+        if !Weak::ptr_eq(call, &Arc::downgrade(call_handler_lock)) {
+            *call = Arc::downgrade(call_handler_lock);
+            *clock = Arc::new(SessionClock::new());
+        }
+        clock.clone()
+    }
+}
+
+/// Leaves that are waiting for the grace period to pass, by guild.
+#[derive(Clone, Default)]
+pub struct PendingLeaves {
+    inner: Arc<DashMap<GuildId, JoinHandle<()>>>,
+}
+
+impl PendingLeaves {
+    /// Removes the call after the grace period if the channel is still empty by then.
+    /// Does nothing if there is already a pending leave for the guild.
+    pub fn schedule(
+        &self,
+        ctx: Context,
+        songbird: Arc<Songbird>,
+        guild_id: GuildId,
+        grace_period: Duration,
+    ) {
+        let Entry::Vacant(entry) = self.inner.entry(guild_id) else {
+            return;
+        };
+        let pending_leaves = self.clone();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(grace_period).await;
+            // Remove first so the abort in `cancel` can't hit us while leaving.
+            pending_leaves.inner.remove(&guild_id);
+
+            let still_empty = match songbird.get(guild_id) {
+                Some(call_handler_lock) => {
+                    let call_handler = call_handler_lock.lock().await;
+                    call_handler
+                        .current_channel()
+                        .is_none_or(|channel| check_if_channel_empty(&ctx, guild_id, channel))
+                }
+                None => false,
+            };
+            if still_empty {
+                tracing::info!(
+                    "Removing call_handler because the channel stayed empty: {}",
+                    guild_id
+                );
+                if let Err(err) = songbird.remove(guild_id).await {
+                    tracing::error!("Failed to remove call_handler: {:?}", err);
+                }
+            }
+        });
+        entry.insert(handle);
+    }
+
+    pub fn cancel(&self, guild_id: GuildId) {
+        if let Some((_, handle)) = self.inner.remove(&guild_id) {
+            tracing::info!("Someone came back, cancelling the leave: {}", guild_id);
+            handle.abort();
+        }
+    }
+}
+
+/// Removes the call once the session is too long or nothing was triggered for too long.
+/// The task ends by itself when the [`VoiceHandler`] is dropped, which happens when the call is removed
+/// or a new handler replaces it. The new handler keeps the [`SessionClock`] of the call.
+pub fn watch_session(
+    voice_handler: &VoiceHandler,
+    songbird: Arc<Songbird>,
+    guild_id: GuildId,
+    timeouts: VoiceTimeouts,
+) {
+    if timeouts.max_session.is_none() && timeouts.idle_timeout.is_none() {
+        return;
+    }
+    let voice_handler = voice_handler.downgrade();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(voice_handler) = voice_handler.upgrade() else {
+                return;
+            };
+
+            let session_over = timeouts
+                .max_session
+                .is_some_and(|max_session| voice_handler.clock().session_length() >= max_session);
+            let idle = timeouts
+                .idle_timeout
+                .is_some_and(|idle_timeout| voice_handler.clock().idle_time() >= idle_timeout);
+            // Don't keep the handler alive while leaving.
+            drop(voice_handler);
+
+            if session_over || idle {
+                tracing::info!(
+                    "Removing call_handler because the session is over (max session: {}, idle: {}): {}",
+                    session_over,
+                    idle,
+                    guild_id
+                );
+                if let Err(err) = songbird.remove(guild_id).await {
+                    tracing::error!("Failed to remove call_handler: {:?}", err);
+                }
+                return;
+            }
+        }
+    });
+}
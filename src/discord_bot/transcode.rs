@@ -0,0 +1,27 @@
+//! Re-encoding of uploaded sounds to Opus before they are stored.
+//!
+//! The files are written in songbird's DCA format, Opus frames with a small header,
+//! so they can be played without decoding or encoding them again.
+
+use std::io::Read;
+
+use anyhow::Result;
+use songbird::{driver::Bitrate, input::cached::Compressed};
+
+const DCA_MAGIC: &[u8] = b"DCA1";
+
+/// Decodes any format symphonia supports and encodes it to Opus at `bitrate` bits per second.
+pub async fn to_opus(content: Vec<u8>, bitrate: i32) -> Result<Vec<u8>> {
+    let mut compressed = Compressed::new(content.into(), Bitrate::BitsPerSecond(bitrate)).await?;
+    tokio::task::spawn_blocking(move || {
+        let mut encoded = Vec::new();
+        compressed.read_to_end(&mut encoded)?;
+        Ok(encoded)
+    })
+    .await?
+}
+
+/// True if the file was encoded by [`to_opus`]. Files stored before encoding was added are not.
+pub fn is_opus(content: &[u8]) -> bool {
+    content.starts_with(DCA_MAGIC)
+}
@@ -0,0 +1,274 @@
+use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{config::RecognitionSettings, metrics};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the blocking Vosk calls on dedicated threads, so a busy recognizer can't stall songbird's voice tick.
+///
+/// Every speaker has a [`SpeakerQueue`], and is scheduled on a worker while it has work queued.
+/// A speaker is only scheduled once at a time, so its audio is always handled in order.
+pub struct WorkerPool {
+    senders: Vec<Sender<Job>>,
+    /// Frames a [`SpeakerQueue`] holds before the oldest are dropped.
+    queue: usize,
+}
+
+impl WorkerPool {
+    /// One worker per cpu if the number of workers is not set.
+    pub fn from_config(settings: &RecognitionSettings) -> Arc<Self> {
+        let workers = settings
+            .workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |cpus| cpus.get()));
+        Self::new(workers, settings.queue)
+    }
+
+    pub fn new(workers: usize, queue: usize) -> Arc<Self> {
+        let senders = (0..workers.max(1))
+            .map(|index| {
+                let (sender, receiver) = mpsc::channel::<Job>();
+                thread::Builder::new()
+                    .name(format!("recognition-{}", index))
+                    .spawn(move || {
+                        // Ends when the pool is dropped.
+                        while let Ok(job) = receiver.recv() {
+                            // A panicking job must not take the other speakers of this worker with it.
+                            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                                tracing::error!(
+                                    "Recognition job panicked: {}",
+                                    panic_message(&*panic)
+                                );
+                            }
+                        }
+                    })
+                    .expect("Could not spawn a recognition worker");
+                sender
+            })
+            .collect();
+        tracing::info!("Started {} recognition workers", workers.max(1));
+
+        Arc::new(Self { senders, queue })
+    }
+
+    pub fn new_queue(&self) -> SpeakerQueue {
+        SpeakerQueue::new(self.queue)
+    }
+
+    /// Runs the job on the worker of the ssrc.
+    /// The channels are unbounded, but only hold one job per scheduled [`SpeakerQueue`].
+    pub fn execute(&self, ssrc: u32, job: impl FnOnce() + Send + 'static) {
+        let sender = &self.senders[ssrc as usize % self.senders.len()];
+        let _ = sender.send(Box::new(job));
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Work of a single speaker, taken by the worker it is scheduled on.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Work {
+    Audio(Vec<i16>),
+    Finalise,
+}
+
+/// Bounded queue of a speaker's audio.
+///
+/// When it is full the oldest frame is dropped, and counted in [`metrics::FRAMES_DROPPED`].
+/// Finalising is never dropped: it is queued after the audio it finishes, so audio that comes later
+/// goes to the next utterance. Requests without audio in between are coalesced.
+pub struct SpeakerQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    work: VecDeque<Work>,
+    /// Number of [`Work::Audio`] in `work`.
+    frames: usize,
+    /// Audio was queued since the last finalise request.
+    heard: bool,
+    /// A worker is draining the queue.
+    scheduled: bool,
+}
+
+impl QueueState {
+    /// Marks the queue as scheduled. True if it wasn't, and the caller has to schedule it.
+    fn schedule(&mut self) -> bool {
+        !std::mem::replace(&mut self.scheduled, true)
+    }
+}
+
+impl SpeakerQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Returns true if the queue has to be scheduled on a worker.
+    pub fn push_audio(&self, audio: Vec<i16>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.frames >= self.capacity {
+            if let Some(oldest) = state
+                .work
+                .iter()
+                .position(|work| matches!(work, Work::Audio(_)))
+            {
+                state.work.remove(oldest);
+                state.frames -= 1;
+            }
+            metrics::FRAMES_DROPPED.inc();
+            let dropped = metrics::FRAMES_DROPPED.get();
+            // Don't flood the logs under load.
+            if dropped.is_power_of_two() {
+                tracing::warn!(
+                    "Recognition workers are falling behind, {} frames dropped so far",
+                    dropped
+                );
+            }
+        }
+        state.work.push_back(Work::Audio(audio));
+        state.frames += 1;
+        state.heard = true;
+        state.schedule()
+    }
+
+    /// Asks for the utterance to be finalised after the audio queued so far, if there was audio since the last time.
+    /// Returns true if the queue has to be scheduled on a worker.
+    pub fn request_finalise(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !std::mem::take(&mut state.heard) {
+            return false;
+        }
+        state.work.push_back(Work::Finalise);
+        state.schedule()
+    }
+
+    /// The next work for the worker. Once it returns `None` the queue has to be scheduled again.
+    pub fn next(&self) -> Option<Work> {
+        let mut state = self.state.lock().unwrap();
+        let work = state.work.pop_front();
+        match work {
+            Some(Work::Audio(_)) => state.frames -= 1,
+            Some(Work::Finalise) => {}
+            None => state.scheduled = false,
+        }
+        work
+    }
+
+    /// Handles the queued work in order, until the queue is empty.
+    /// If `handle` panics the queue is unscheduled, so the next push schedules it again.
+    pub fn drain(&self, mut handle: impl FnMut(Work)) {
+        let unschedule = Unschedule(self);
+        while let Some(work) = self.next() {
+            handle(work);
+        }
+        std::mem::forget(unschedule);
+    }
+}
+
+/// Unschedules the queue when dropped during a panic in [`SpeakerQueue::drain`].
+struct Unschedule<'a>(&'a SpeakerQueue);
+
+impl Drop for Unschedule<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.scheduled = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_queue_drops_the_oldest_audio() {
+        let queue = SpeakerQueue::new(2);
+        assert!(queue.push_audio(vec![1]));
+        assert!(!queue.push_audio(vec![2]));
+        assert!(!queue.push_audio(vec![3]));
+
+        assert_eq!(queue.next(), Some(Work::Audio(vec![2])));
+        assert_eq!(queue.next(), Some(Work::Audio(vec![3])));
+        assert_eq!(queue.next(), None);
+    }
+
+    #[test]
+    fn finalise_is_coalesced_and_never_dropped() {
+        let queue = SpeakerQueue::new(1);
+        // Nothing was heard, there is nothing to finalise.
+        assert!(!queue.request_finalise());
+        assert_eq!(queue.next(), None);
+
+        assert!(queue.push_audio(vec![1]));
+        assert!(!queue.request_finalise());
+        assert!(!queue.request_finalise());
+        // Drops the audio, not the finalise.
+        assert!(!queue.push_audio(vec![2]));
+
+        assert_eq!(queue.next(), Some(Work::Finalise));
+        assert_eq!(queue.next(), Some(Work::Audio(vec![2])));
+        assert_eq!(queue.next(), None);
+    }
+
+    #[test]
+    fn audio_after_a_finalise_goes_to_the_next_utterance() {
+        let queue = SpeakerQueue::new(4);
+        assert!(queue.push_audio(vec![1]));
+        assert!(!queue.request_finalise());
+        assert!(!queue.push_audio(vec![2]));
+        assert!(!queue.request_finalise());
+
+        assert_eq!(queue.next(), Some(Work::Audio(vec![1])));
+        assert_eq!(queue.next(), Some(Work::Finalise));
+        assert_eq!(queue.next(), Some(Work::Audio(vec![2])));
+        assert_eq!(queue.next(), Some(Work::Finalise));
+        assert_eq!(queue.next(), None);
+    }
+
+    #[test]
+    fn drained_queue_is_scheduled_again() {
+        let queue = SpeakerQueue::new(4);
+        assert!(queue.push_audio(vec![1]));
+        assert_eq!(queue.next(), Some(Work::Audio(vec![1])));
+        assert_eq!(queue.next(), None);
+
+        assert!(queue.request_finalise());
+        assert_eq!(queue.next(), Some(Work::Finalise));
+        assert_eq!(queue.next(), None);
+    }
+
+    #[test]
+    fn panicking_work_unschedules_the_queue() {
+        let pool = WorkerPool::new(1, 4);
+        let queue = Arc::new(pool.new_queue());
+        assert!(queue.push_audio(vec![1]));
+
+        let (sender, receiver) = mpsc::channel();
+        let panicking = queue.clone();
+        pool.execute(1, move || panicking.drain(|_| panic!("recognizer failed")));
+        // The worker survives and runs the next job.
+        pool.execute(1, move || sender.send(()).unwrap());
+        receiver.recv().unwrap();
+
+        assert!(queue.push_audio(vec![2]));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt::Write, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use prometheus::{Encoder, TextEncoder};
+use serenity::all::{ConnectionStage, ShardManager};
+use songbird::Songbird;
+
+use crate::{database::Database, discord_bot::ModelStore, metrics};
+
+#[derive(Clone)]
+pub struct HttpState {
+    pub songbird: Arc<Songbird>,
+    pub shard_manager: Arc<ShardManager>,
+    pub database: Arc<Database>,
+    pub models: ModelStore,
+}
+
+/// Serves the metrics and the health checks in the background. The server stops with the runtime.
+pub fn spawn(addr: SocketAddr, state: HttpState) {
+    let app = Router::new()
+        .route("/metrics", get(serve_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state);
+
+    tokio::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("Could not bind the http server to {}: {:?}", addr, err);
+                return;
+            }
+        };
+        tracing::info!("Http server listening on {}", addr);
+        if let Err(err) = axum::serve(listener, app).await {
+            tracing::error!("Http server stopped: {:?}", err);
+        }
+    });
+}
+
+async fn serve_metrics(State(state): State<HttpState>) -> impl IntoResponse {
+    metrics::ACTIVE_CALLS.set(state.songbird.iter().count() as i64);
+    (
+        [(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )],
+        metrics::gather(),
+    )
+}
+
+/// The process is up and the runtime is responsive.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Ready once every shard is connected to the gateway, the models are loaded and the database answers.
+/// Lists every check so a failing probe shows what is wrong.
+async fn readyz(State(state): State<HttpState>) -> impl IntoResponse {
+    let gateway = {
+        let runners = state.shard_manager.runners.lock().await;
+        !runners.is_empty()
+            && runners
+                .values()
+                .all(|runner| runner.stage == ConnectionStage::Connected)
+    };
+    let models = !state.models.current().is_empty();
+    let database = match state.database.ping().await {
+        Ok(()) => true,
+        Err(err) => {
+            tracing::warn!("Database ping failed: {:?}", err);
+            false
+        }
+    };
+
+    let mut body = String::new();
+    for (check, ok) in [
+        ("gateway", gateway),
+        ("models", models),
+        ("database", database),
+    ] {
+        let _ = writeln!(body, "{}: {}", check, if ok { "ok" } else { "failing" });
+    }
+    let status = if gateway && models && database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, body)
+}
@@ -1,3 +1,9 @@
 pub mod discord_bot;
+pub mod resample;
 pub mod speech_to_text;
 pub mod database;
+pub mod voice_activity;
+pub mod http_server;
+pub mod metrics;
+pub mod logging;
+pub mod config;
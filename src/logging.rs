@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::config::{LogFormat, LoggingSettings};
+
+static LOG_TRANSCRIPTS: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the global subscriber.
+///
+/// - `RUST_LOG` filters the events, the configured filter is used if it is not set.
+/// - The json format prints one json object per line.
+/// - Transcripts log what the recognizers heard. They are off by default so nobody's speech ends up in the logs.
+pub fn init(settings: &LoggingSettings) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&settings.filter));
+    let subscriber = fmt().with_env_filter(filter);
+    match settings.format {
+        LogFormat::Json => subscriber.json().init(),
+        LogFormat::Text => subscriber.init(),
+    }
+
+    LOG_TRANSCRIPTS.store(settings.transcripts, Ordering::Relaxed);
+    if settings.transcripts {
+        tracing::warn!("Transcripts are logged");
+    }
+}
+
+pub fn transcripts_enabled() -> bool {
+    LOG_TRANSCRIPTS.load(Ordering::Relaxed)
+}
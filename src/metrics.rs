@@ -0,0 +1,87 @@
+//! Prometheus metrics, served on `/metrics` by [`crate::http_server`].
+
+use std::sync::LazyLock;
+
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramTimer, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+pub static ACTIVE_CALLS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("verstappen_active_calls", "Voice calls the bot is in").unwrap()
+});
+
+pub static LISTENERS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "verstappen_listeners",
+        "Speakers with recognizers, by guild",
+        &["guild_id"]
+    )
+    .unwrap()
+});
+
+pub static FRAMES_RECOGNIZED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "verstappen_frames_recognized_total",
+        "Frames fed to the recognizers"
+    )
+    .unwrap()
+});
+
+pub static FRAMES_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "verstappen_frames_dropped_total",
+        "Frames dropped because the recognition workers fell behind"
+    )
+    .unwrap()
+});
+
+pub static FINALISE_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "verstappen_finalise_seconds",
+        "Time it takes a recognizer to finalise an utterance",
+        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap()
+});
+
+pub static TRIGGERS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "verstappen_triggers_total",
+        "Sounds played because their prompt was recognized, by language",
+        &["language"]
+    )
+    .unwrap()
+});
+
+pub static PLAYBACK_ERRORS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "verstappen_playback_errors_total",
+        "Sounds that failed to play"
+    )
+    .unwrap()
+});
+
+pub static DB_QUERY_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "verstappen_db_query_seconds",
+        "Database query latency, by query",
+        &["query"]
+    )
+    .unwrap()
+});
+
+/// Observes the query's latency when dropped.
+pub fn db_timer(query: &str) -> HistogramTimer {
+    DB_QUERY_SECONDS.with_label_values(&[query]).start_timer()
+}
+
+/// Every registered metric in the Prometheus text format.
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Could not encode the metrics");
+    String::from_utf8(buffer).expect("Metrics are not valid utf-8")
+}
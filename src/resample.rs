@@ -0,0 +1,270 @@
+use songbird::constants::MONO_FRAME_SIZE;
+
+/// Sample rate of the audio that songbird decodes.
+pub const INPUT_SAMPLE_RATE: u32 = 48000;
+
+/// Number of interleaved channels in a tick of decoded audio.
+/// Songbird 0.4 always decodes to stereo, but a tick is always 20 ms so the count follows from its length.
+pub fn channels_of(data: &[i16]) -> usize {
+    (data.len() / MONO_FRAME_SIZE).max(1)
+}
+
+/// Mono audio of a single tick from one speaker.
+/// Every sample rate is only resampled once per tick, no matter how many recognizers ask for it.
+/// The buffers are reused between ticks.
+#[derive(Default)]
+pub struct MonoFrame {
+    mono: Vec<i16>,
+    resampled: Vec<Resampled>,
+}
+
+struct Resampled {
+    sample_rate: u32,
+    samples: Vec<i16>,
+    /// False if the samples are from an earlier tick.
+    fresh: bool,
+}
+
+impl MonoFrame {
+    /// Replaces the frame with the next tick's audio, interleaved with `channels` channels.
+    pub fn fill(&mut self, data: &[i16], channels: usize) {
+        downmix(data, channels, &mut self.mono);
+        for resampled in self.resampled.iter_mut() {
+            resampled.fresh = false;
+        }
+    }
+
+    pub fn mono(&self) -> &[i16] {
+        &self.mono
+    }
+
+    pub fn at_rate(&mut self, sample_rate: u32) -> &[i16] {
+        if sample_rate == INPUT_SAMPLE_RATE {
+            return &self.mono;
+        }
+        let index = match self
+            .resampled
+            .iter()
+            .position(|resampled| resampled.sample_rate == sample_rate)
+        {
+            Some(index) => index,
+            None => {
+                self.resampled.push(Resampled {
+                    sample_rate,
+                    samples: Vec::new(),
+                    fresh: false,
+                });
+                self.resampled.len() - 1
+            }
+        };
+        let resampled = &mut self.resampled[index];
+        if !resampled.fresh {
+            resample(
+                &self.mono,
+                INPUT_SAMPLE_RATE,
+                sample_rate,
+                &mut resampled.samples,
+            );
+            resampled.fresh = true;
+        }
+        &resampled.samples
+    }
+}
+
+/// Averages the channels of interleaved audio into `output`, replacing its contents.
+pub fn downmix(input: &[i16], channels: usize, output: &mut Vec<i16>) {
+    match channels {
+        1 => {
+            output.clear();
+            output.extend_from_slice(input);
+        }
+        2 => stereo_to_mono(input, output),
+        _ => {
+            output.clear();
+            output.extend(input.chunks_exact(channels).map(|frame| {
+                let sum: i32 = frame.iter().map(|&sample| sample as i32).sum();
+                sum.div_euclid(channels as i32) as i16
+            }));
+        }
+    }
+}
+
+/// Averages the left and right samples into `output`, replacing its contents.
+/// Uses SSE2 on x86_64, which every x86_64 cpu has.
+pub fn stereo_to_mono(input: &[i16], output: &mut Vec<i16>) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SAFETY: SSE2 is part of the x86_64 baseline.
+        unsafe { stereo_to_mono_sse2(input, output) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    stereo_to_mono_scalar(input, output);
+}
+
+/// Portable version of [`stereo_to_mono`].
+/// The sum is computed in i32 so nothing is lost before halving, and it is rounded down like the SIMD version.
+pub fn stereo_to_mono_scalar(input: &[i16], output: &mut Vec<i16>) {
+    output.clear();
+    output.extend(
+        input
+            .chunks_exact(2)
+            .map(|pair| ((pair[0] as i32 + pair[1] as i32) >> 1) as i16),
+    );
+}
+
+/// Handles 8 stereo pairs at a time: `madd` with ones sums every pair into an i32,
+/// the shift halves it and `packs` narrows the results back to i16.
+/// The leftover pairs go through [`stereo_to_mono_scalar`].
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn stereo_to_mono_sse2(input: &[i16], output: &mut Vec<i16>) {
+    use std::arch::x86_64::{
+        __m128i, _mm_loadu_si128, _mm_madd_epi16, _mm_packs_epi32, _mm_set1_epi16, _mm_srai_epi32,
+        _mm_storeu_si128,
+    };
+
+    let pairs = input.len() / 2;
+    output.clear();
+    output.resize(pairs, 0);
+
+    let ones = _mm_set1_epi16(1);
+    let blocks = pairs / 8;
+    for block in 0..blocks {
+        let source = input.as_ptr().add(block * 16);
+        let low = _mm_loadu_si128(source as *const __m128i);
+        let high = _mm_loadu_si128(source.add(8) as *const __m128i);
+        let low = _mm_srai_epi32(_mm_madd_epi16(low, ones), 1);
+        let high = _mm_srai_epi32(_mm_madd_epi16(high, ones), 1);
+        let mono = _mm_packs_epi32(low, high);
+        _mm_storeu_si128(output.as_mut_ptr().add(block * 8) as *mut __m128i, mono);
+    }
+
+    for (index, pair) in input[blocks * 16..].chunks_exact(2).enumerate() {
+        output[blocks * 8 + index] = ((pair[0] as i32 + pair[1] as i32) >> 1) as i16;
+    }
+}
+
+/// Resamples mono audio into `output`, replacing its contents.
+/// Averages every n samples if the rates divide evenly (48 kHz to 16 kHz or 8 kHz),
+/// falls back to linear interpolation otherwise.
+/// Each tick is resampled on its own, 20 ms of 48 kHz audio divides evenly for the usual model rates.
+pub fn resample(input: &[i16], from: u32, to: u32, output: &mut Vec<i16>) {
+    output.clear();
+    if from == to || input.is_empty() {
+        output.extend_from_slice(input);
+        return;
+    }
+    if from > to && from.is_multiple_of(to) {
+        let factor = (from / to) as usize;
+        output.extend(input.chunks(factor).map(|chunk| {
+            let sum: i32 = chunk.iter().map(|&sample| sample as i32).sum();
+            (sum / chunk.len() as i32) as i16
+        }));
+        return;
+    }
+
+    let output_len = (input.len() as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    output.extend((0..output_len).map(|index| {
+        let position = index as f64 * step;
+        let left = position as usize;
+        let right = (left + 1).min(input.len() - 1);
+        let fraction = position - left as f64;
+        (input[left] as f64 * (1.0 - fraction) + input[right] as f64 * fraction) as i16
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simd_downmix_matches_the_scalar_one() {
+        let extremes = [
+            i16::MIN,
+            i16::MAX,
+            i16::MIN,
+            i16::MIN,
+            i16::MAX,
+            i16::MAX,
+            -1,
+            0,
+        ];
+        // Odd lengths and lengths that don't fill the last block of 8 pairs.
+        for len in [0, 1, 2, 3, 15, 16, 17, 31, 33, 1919, 1920, 1921] {
+            let input: Vec<i16> = (0..len)
+                .map(|index| match index % 5 {
+                    0 | 1 => extremes[index % extremes.len()],
+                    _ => ((index * 7919) % u16::MAX as usize) as i16,
+                })
+                .collect();
+            let mut simd = Vec::new();
+            let mut scalar = Vec::new();
+            stereo_to_mono(&input, &mut simd);
+            stereo_to_mono_scalar(&input, &mut scalar);
+            assert_eq!(simd, scalar, "length {}", len);
+            assert_eq!(simd.len(), len / 2);
+        }
+
+        let mut output = Vec::new();
+        stereo_to_mono(&[i16::MIN; 18], &mut output);
+        assert_eq!(output, [i16::MIN; 9]);
+        stereo_to_mono(&[i16::MAX; 18], &mut output);
+        assert_eq!(output, [i16::MAX; 9]);
+    }
+
+    #[test]
+    fn channels_follow_the_frame_length() {
+        let stereo = vec![0; MONO_FRAME_SIZE * 2];
+        let mono: Vec<i16> = (0..MONO_FRAME_SIZE as i16).collect();
+        assert_eq!(channels_of(&stereo), 2);
+        assert_eq!(channels_of(&mono), 1);
+
+        let mut frame = MonoFrame::default();
+        frame.fill(&mono, channels_of(&mono));
+        assert_eq!(frame.mono(), &mono[..]);
+        frame.fill(&stereo, channels_of(&stereo));
+        assert_eq!(frame.mono().len(), MONO_FRAME_SIZE);
+    }
+
+    #[test]
+    fn evenly_divided_rates_average_the_samples() {
+        let input: Vec<i16> = (0..MONO_FRAME_SIZE as i16).map(|index| index * 3).collect();
+        let mut output = Vec::new();
+        resample(&input, INPUT_SAMPLE_RATE, 16000, &mut output);
+
+        assert_eq!(output.len(), MONO_FRAME_SIZE / 3);
+        // The average of 3k, 3k + 3 and 3k + 6.
+        for (index, &sample) in output.iter().enumerate() {
+            assert_eq!(sample, index as i16 * 9 + 3);
+        }
+
+        resample(&[i16::MAX, i16::MAX, i16::MIN], 48000, 16000, &mut output);
+        assert_eq!(output, [(i16::MAX as i32 * 2 + i16::MIN as i32) as i16 / 3]);
+    }
+
+    #[test]
+    fn other_rates_are_interpolated() {
+        let input: Vec<i16> = (0..MONO_FRAME_SIZE as i16)
+            .map(|index| index * 10)
+            .collect();
+        let mut output = Vec::new();
+        resample(&input, INPUT_SAMPLE_RATE, 44100, &mut output);
+
+        assert_eq!(output.len(), MONO_FRAME_SIZE * 44100 / 48000);
+        // A ramp stays a ramp, at the position of each output sample in the input.
+        for (index, &sample) in output.iter().enumerate() {
+            let expected = index as f64 * 48000.0 / 44100.0 * 10.0;
+            assert!(
+                (sample as f64 - expected).abs() <= 1.0,
+                "sample {} is {}, expected {}",
+                index,
+                sample,
+                expected
+            );
+        }
+
+        resample(&[100, 200], 16000, 48000, &mut output);
+        assert_eq!(output, [100, 133, 166, 200, 200, 200]);
+    }
+}
@@ -1,22 +1,30 @@
-use vosk::{CompleteResult, Model, Recognizer};
+use std::{fs, path::Path, time::Duration};
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+use serde::Deserialize;
+
+use vosk::{CompleteResult, DecodingState, Model, Recognizer};
+
+use crate::{
+    config::{LanguageModeName, RecognitionSettings},
+    logging, metrics,
+    voice_activity::VadConfig,
+};
+
+#[derive(
+    PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, poise::ChoiceParameter, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum ModelLanguage {
+    #[name = "english"]
     ENGLISH,
+    #[name = "turkish"]
     TURKISH,
+    #[name = "dutch"]
     DUTCH,
 }
 
 impl ModelLanguage {
-    pub fn from_str(language: &str) -> Option<Self> {
-        match language {
-            "english" => Some(Self::ENGLISH),
-            "turkish" => Some(Self::TURKISH),
-            "dutch" => Some(Self::DUTCH),
-            _ => None,
-        }
-    }
-    pub fn to_str(&self) -> &str {
+    pub fn to_str(&self) -> &'static str {
         match self {
             Self::ENGLISH => "english",
             Self::TURKISH => "turkish",
@@ -25,24 +33,187 @@ impl ModelLanguage {
     }
 }
 
+/// Vosk models are usually trained on 16 kHz audio.
+const DEFAULT_MODEL_SAMPLE_RATE: u32 = 16000;
+
+/// Reads the sample rate the model was trained on from `mfcc.conf`.
+/// Most models keep it under `conf/`, some ship it at the root of the model.
+pub fn model_sample_rate(model_path: &str) -> u32 {
+    let model_path = Path::new(model_path);
+    let conf_paths = [
+        model_path.join("conf").join("mfcc.conf"),
+        model_path.join("mfcc.conf"),
+    ];
+    let Some(conf) = conf_paths
+        .iter()
+        .find_map(|conf_path| fs::read_to_string(conf_path).ok())
+    else {
+        tracing::warn!(
+            "Could not read the mfcc.conf of {}, assuming {} Hz",
+            model_path.display(),
+            DEFAULT_MODEL_SAMPLE_RATE
+        );
+        return DEFAULT_MODEL_SAMPLE_RATE;
+    };
+    conf.lines()
+        .find_map(|line| line.trim().strip_prefix("--sample-frequency="))
+        .and_then(|rate| rate.trim().parse::<f32>().ok())
+        .map(|rate| rate as u32)
+        .unwrap_or(DEFAULT_MODEL_SAMPLE_RATE)
+}
+
+/// Limits for a single utterance, so someone who never goes silent doesn't grow the recognizer forever.
+/// Trailing silence is handled by the [`crate::voice_activity::VoiceActivityDetector`], quiet frames never get here.
+#[derive(Clone, Copy)]
+pub struct UtteranceLimits {
+    /// Finalise the utterance once it gets this long.
+    pub max_utterance: Duration,
+}
+
+impl UtteranceLimits {
+    pub fn from_config(settings: &RecognitionSettings) -> Self {
+        Self {
+            max_utterance: Duration::from_secs(settings.max_utterance_secs),
+        }
+    }
+}
+
+/// Which recognizers a speaker's audio goes to.
+#[derive(Clone, Copy)]
+pub enum LanguageMode {
+    /// Every language that has sounds.
+    All,
+    /// Every language until the spoken one is identified, then only that one, see [`LanguageIdentifier`].
+    Identify {
+        /// Average confidence an utterance needs to identify the language.
+        min_confidence: f32,
+    },
+}
+
+impl LanguageMode {
+    pub fn from_config(settings: &RecognitionSettings) -> Self {
+        match settings.language_mode {
+            LanguageModeName::All => Self::All,
+            LanguageModeName::Identify => Self::Identify {
+                min_confidence: settings.identify_confidence,
+            },
+        }
+    }
+}
+
+/// Utterances in a row that have to match the same language before the others are dropped.
+const IDENTIFY_MATCHES: usize = 3;
+/// Utterances in a row that match nothing before every language is listened to again.
+const RESTORE_AFTER_MISSES: usize = 10;
+
+/// Decides which language a speaker speaks, from the languages their utterances matched.
+///
+/// A single false positive doesn't lock the speaker to a language: it takes [`IDENTIFY_MATCHES`] matches in a row,
+/// and [`RESTORE_AFTER_MISSES`] utterances that match nothing bring back every language.
+#[derive(Default)]
+pub struct LanguageIdentifier {
+    identified: Option<ModelLanguage>,
+    /// The language of the last matches, and how many there were in a row.
+    streak: Option<(ModelLanguage, usize)>,
+    /// Utterances in a row that matched nothing since the language was identified.
+    misses: usize,
+}
+
+impl LanguageIdentifier {
+    pub fn identified(&self) -> Option<ModelLanguage> {
+        self.identified
+    }
+
+    pub fn listens_to(&self, language: ModelLanguage) -> bool {
+        self.identified
+            .is_none_or(|identified| identified == language)
+    }
+
+    /// Records a finished utterance and the language it matched, if any.
+    /// Returns true if the languages that are listened to changed.
+    pub fn utterance(&mut self, matched: Option<ModelLanguage>) -> bool {
+        match (self.identified, matched) {
+            (None, Some(language)) => {
+                let matches = match self.streak {
+                    Some((streak, matches)) if streak == language => matches + 1,
+                    _ => 1,
+                };
+                if matches < IDENTIFY_MATCHES {
+                    self.streak = Some((language, matches));
+                    return false;
+                }
+                self.identified = Some(language);
+                self.streak = None;
+                self.misses = 0;
+                true
+            }
+            // Utterances that match nothing don't break the streak.
+            (None, None) => false,
+            (Some(identified), Some(language)) if identified == language => {
+                self.misses = 0;
+                false
+            }
+            (Some(_), _) => {
+                self.misses += 1;
+                if self.misses < RESTORE_AFTER_MISSES {
+                    return false;
+                }
+                self.identified = None;
+                self.misses = 0;
+                true
+            }
+        }
+    }
+}
+
+/// Recognition settings shared by every guild.
+#[derive(Clone, Copy)]
+pub struct RecognitionConfig {
+    pub limits: UtteranceLimits,
+    pub vad: VadConfig,
+    pub language_mode: LanguageMode,
+}
+
+impl RecognitionConfig {
+    pub fn from_config(settings: &RecognitionSettings) -> Self {
+        Self {
+            limits: UtteranceLimits::from_config(settings),
+            vad: VadConfig::from_config(settings),
+            language_mode: LanguageMode::from_config(settings),
+        }
+    }
+}
+
 pub struct SpeechToText {
     recognizer: Recognizer,
     active: bool,
     words: Vec<String>,
     phrases: Vec<String>,
     language: ModelLanguage,
+    sample_rate: u32,
+    limits: UtteranceLimits,
+    /// Mono samples fed since the last finalisation.
+    utterance_samples: usize,
+    /// Average confidence of the last finished utterance, until it is taken. 0 if no words were heard.
+    score: Option<f32>,
 }
 
 impl SpeechToText {
     pub fn new_with_grammar(
         model: &Model,
         language: ModelLanguage,
+        sample_rate: u32,
         words: &[String],
         phrases: &[String],
+        limits: UtteranceLimits,
     ) -> Self {
         let grammar: Vec<String> = words.iter().chain(phrases.iter()).cloned().collect();
-        dbg!(&grammar);
-        let mut recognizer = Recognizer::new_with_grammar(model, 48000., &grammar)
+        tracing::debug!(
+            language = language.to_str(),
+            ?grammar,
+            "Creating a recognizer"
+        );
+        let mut recognizer = Recognizer::new_with_grammar(model, sample_rate as f32, &grammar)
             .expect("Could not create the Recognizer");
         recognizer.set_words(true);
         Self {
@@ -51,48 +222,160 @@ impl SpeechToText {
             words: words.to_vec(),
             phrases: phrases.to_vec(),
             language,
+            sample_rate,
+            limits,
+            utterance_samples: 0,
+            score: None,
         }
     }
 
-    pub fn listen(&mut self, data: &[i16]) {
-        let data = stereo_to_mono(data);
-        self.recognizer.accept_waveform(&data);
+    pub fn language(&self) -> ModelLanguage {
+        self.language
+    }
+
+    /// How sure the recognizer was about the words of the last utterance, `None` if no utterance finished since.
+    pub fn take_score(&mut self) -> Option<f32> {
+        self.score.take()
+    }
+
+    /// Mono audio has to be fed at this rate.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Feeds the mono audio to the recognizer. The utterance is finalised early if Vosk detects an endpoint
+    /// or if it got too long.
+    pub fn listen(&mut self, data: &[i16]) -> Option<(String, ModelLanguage)> {
+        let state = self.recognizer.accept_waveform(data);
         self.active = true;
+
+        self.utterance_samples += data.len();
+
+        if state == DecodingState::Finalized {
+            self.active = false;
+            self.utterance_samples = 0;
+            let result = self.recognizer.result();
+            self.score = Some(score(&result));
+            return find_prompt(&self.words, &self.phrases, self.language, result);
+        }
+
+        if self.utterance_samples
+            >= duration_to_samples(self.limits.max_utterance, self.sample_rate)
+        {
+            // `final_result` resets the recognizer for the next utterance.
+            return self.finalise();
+        }
+        None
     }
 
-    // be cautious as there are a lot of "word" here.
-    // One is Vosk result word, other one is words we are looking for.
     pub fn finalise(&mut self) -> Option<(String, ModelLanguage)> {
+        self.utterance_samples = 0;
         if self.active {
             self.active = false;
+            let _timer = metrics::FINALISE_SECONDS.start_timer();
             let result = self.recognizer.final_result();
-            if let CompleteResult::Single(result) = result {
-                let word_result = result.result.iter().find(|word| {
-                    println!("{:?}", word);
-                    word.conf > 0.999 && self.words.iter().any(|w| w == word.word)
-                });
-                if let Some(word) = word_result {
-                    return Some((word.word.to_string(), self.language));
-                }
+            self.score = Some(score(&result));
+            return find_prompt(&self.words, &self.phrases, self.language, result);
+        }
+        None
+    }
+}
 
-                for phrase in &self.phrases {
-                    if result.text.contains(phrase) {
-                        return Some((phrase.to_string(), self.language));
-                    }
-                }
+// be cautious as there are a lot of "word" here.
+// One is Vosk result word, other one is words we are looking for.
+fn find_prompt(
+    words: &[String],
+    phrases: &[String],
+    language: ModelLanguage,
+    result: CompleteResult,
+) -> Option<(String, ModelLanguage)> {
+    if let CompleteResult::Single(result) = result {
+        if logging::transcripts_enabled() {
+            tracing::info!(
+                language = language.to_str(),
+                text = result.text,
+                words = ?result.result,
+                "Transcript"
+            );
+        }
+        let word_result = result
+            .result
+            .iter()
+            .find(|word| word.conf > 0.999 && words.iter().any(|w| w == word.word));
+        if let Some(word) = word_result {
+            return Some((word.word.to_string(), language));
+        }
+
+        for phrase in phrases {
+            if result.text.contains(phrase) {
+                return Some((phrase.to_string(), language));
             }
         }
-        None
     }
+    None
+}
+
+/// Average confidence of the recognized words, words outside of the grammar don't count.
+fn score(result: &CompleteResult) -> f32 {
+    let CompleteResult::Single(result) = result else {
+        return 0.0;
+    };
+    let confidences: Vec<f32> = result
+        .result
+        .iter()
+        .filter(|word| word.word != "[unk]")
+        .map(|word| word.conf)
+        .collect();
+    if confidences.is_empty() {
+        return 0.0;
+    }
+    confidences.iter().sum::<f32>() / confidences.len() as f32
+}
+
+fn duration_to_samples(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64) as usize
 }
 
-pub fn stereo_to_mono(input_data: &[i16]) -> Vec<i16> {
-    let mut result = Vec::with_capacity(input_data.len() / 2);
-    result.extend(
-        input_data
-            .chunks_exact(2)
-            .map(|chunk| chunk[0] / 2 + chunk[1] / 2),
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifying_takes_consistent_matches() {
+        let mut identifier = LanguageIdentifier::default();
+        assert!(!identifier.utterance(Some(ModelLanguage::DUTCH)));
+        // A match of another language starts over.
+        assert!(!identifier.utterance(Some(ModelLanguage::ENGLISH)));
+        for _ in 1..IDENTIFY_MATCHES {
+            assert!(!identifier.utterance(Some(ModelLanguage::DUTCH)));
+            assert!(!identifier.utterance(None));
+        }
+        assert!(identifier.listens_to(ModelLanguage::ENGLISH));
+
+        assert!(identifier.utterance(Some(ModelLanguage::DUTCH)));
+        assert_eq!(identifier.identified(), Some(ModelLanguage::DUTCH));
+        assert!(identifier.listens_to(ModelLanguage::DUTCH));
+        assert!(!identifier.listens_to(ModelLanguage::ENGLISH));
+    }
+
+    #[test]
+    fn misses_restore_every_language() {
+        let mut identifier = LanguageIdentifier::default();
+        for _ in 0..IDENTIFY_MATCHES {
+            identifier.utterance(Some(ModelLanguage::TURKISH));
+        }
+        assert_eq!(identifier.identified(), Some(ModelLanguage::TURKISH));
 
-    result
+        for _ in 1..RESTORE_AFTER_MISSES {
+            assert!(!identifier.utterance(None));
+        }
+        // A match resets the count.
+        assert!(!identifier.utterance(Some(ModelLanguage::TURKISH)));
+        for _ in 1..RESTORE_AFTER_MISSES {
+            assert!(!identifier.utterance(None));
+        }
+        assert!(identifier.utterance(None));
+        assert_eq!(identifier.identified(), None);
+        assert!(identifier.listens_to(ModelLanguage::ENGLISH));
+    }
 }
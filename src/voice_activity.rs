@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use crate::config::RecognitionSettings;
+
+/// Length of the audio in a single songbird tick.
+const FRAME_LENGTH: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy)]
+pub struct VadConfig {
+    /// Frames with a lower RMS than this are not speech. 0 disables the detector.
+    pub threshold: f32,
+    /// How long quiet frames are still passed after speech, so the ends of the words are not cut.
+    pub hangover: Duration,
+}
+
+impl VadConfig {
+    pub fn from_config(settings: &RecognitionSettings) -> Self {
+        Self {
+            threshold: settings.vad_threshold,
+            hangover: Duration::from_millis(settings.vad_hangover_ms),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum VoiceActivity {
+    Speech,
+    /// First non-speech frame after speech, the utterance can be finalised.
+    SpeechEnded,
+    Silence,
+}
+
+/// Energy based voice activity detection, one per speaker.
+pub struct VoiceActivityDetector {
+    config: VadConfig,
+    hangover_frames: u32,
+    /// Frames left until the hangover after the last speech frame runs out.
+    /// `None` if there was no speech since the last [`VoiceActivity::SpeechEnded`].
+    remaining_hangover: Option<u32>,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(config: VadConfig) -> Self {
+        Self {
+            config,
+            hangover_frames: (config.hangover.as_millis() / FRAME_LENGTH.as_millis()) as u32,
+            remaining_hangover: None,
+        }
+    }
+
+    pub fn detect(&mut self, frame: &[i16]) -> VoiceActivity {
+        if self.config.threshold <= 0.0 || rms(frame) >= self.config.threshold {
+            self.remaining_hangover = Some(self.hangover_frames);
+            return VoiceActivity::Speech;
+        }
+        match self.remaining_hangover {
+            Some(0) => {
+                self.remaining_hangover = None;
+                VoiceActivity::SpeechEnded
+            }
+            Some(remaining) => {
+                self.remaining_hangover = Some(remaining - 1);
+                VoiceActivity::Speech
+            }
+            None => VoiceActivity::Silence,
+        }
+    }
+}
+
+/// Root mean square of the samples, a rough measure of loudness.
+pub fn rms(data: &[i16]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = data.iter().map(|&sample| (sample as f64).powi(2)).sum();
+    (sum / data.len() as f64).sqrt() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: [i16; 4] = [1000, -1000, 1000, -1000];
+    const QUIET: [i16; 4] = [10, -10, 10, -10];
+
+    fn detector(threshold: f32, hangover_ms: u64) -> VoiceActivityDetector {
+        VoiceActivityDetector::new(VadConfig {
+            threshold,
+            hangover: Duration::from_millis(hangover_ms),
+        })
+    }
+
+    #[test]
+    fn quiet_frames_pass_during_the_hangover() {
+        // 60 ms is 3 frames.
+        let mut vad = detector(300.0, 60);
+        assert_eq!(vad.detect(&QUIET), VoiceActivity::Silence);
+        assert_eq!(vad.detect(&LOUD), VoiceActivity::Speech);
+        for _ in 0..3 {
+            assert_eq!(vad.detect(&QUIET), VoiceActivity::Speech);
+        }
+        assert_eq!(vad.detect(&QUIET), VoiceActivity::SpeechEnded);
+
+        // Speech during the hangover starts it over.
+        assert_eq!(vad.detect(&LOUD), VoiceActivity::Speech);
+        assert_eq!(vad.detect(&QUIET), VoiceActivity::Speech);
+        assert_eq!(vad.detect(&LOUD), VoiceActivity::Speech);
+        for _ in 0..3 {
+            assert_eq!(vad.detect(&QUIET), VoiceActivity::Speech);
+        }
+        assert_eq!(vad.detect(&QUIET), VoiceActivity::SpeechEnded);
+    }
+
+    #[test]
+    fn speech_ends_once() {
+        let mut vad = detector(300.0, 0);
+        assert_eq!(vad.detect(&LOUD), VoiceActivity::Speech);
+        let activities: Vec<_> = (0..10).map(|_| vad.detect(&QUIET)).collect();
+        assert_eq!(activities[0], VoiceActivity::SpeechEnded);
+        assert!(activities[1..]
+            .iter()
+            .all(|activity| *activity == VoiceActivity::Silence));
+    }
+
+    #[test]
+    fn zero_threshold_disables_the_detector() {
+        let mut vad = detector(0.0, 60);
+        for frame in [&QUIET[..], &[0; 4], &[]] {
+            assert_eq!(vad.detect(frame), VoiceActivity::Speech);
+        }
+    }
+}